@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+// how a value moves from one keyframe to the next
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    // cubic Bézier with automatic handles, smooth through the keyframes and easing in and
+    // out at the first and the last one
+    Bezier,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterpolationParseError(String);
+
+impl std::fmt::Display for InterpolationParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown interpolation `{}`, use linear or bezier", self.0)
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = InterpolationParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Interpolation::Linear),
+            "bezier" => Ok(Interpolation::Bezier),
+            _ => Err(InterpolationParseError(s.to_string()))
+        }
+    }
+}
+
+// weights of the keyframes around `time`, so anything that can be scaled and added is
+// interpolated the same way. `times` are sorted and the interpolation of a keyframe
+// applies until the next one, values are held before the first and after the last
+pub fn weights(times: &[f32], interpolations: &[Interpolation], time: f32) -> [(usize, f32); 4] {
+    let mut weights = [(0, 0.0); 4];
+    let next = times.partition_point(|&t| t <= time);
+    if next == 0 || next == times.len() {
+        weights[0] = (next.saturating_sub(1), 1.0);
+        return weights
+    }
+    let i = next - 1;
+    let (t0, t1) = (times[i], times[i + 1]);
+    let f = (time - t0) / (t1 - t0);
+    match interpolations[i] {
+        Interpolation::Linear => {
+            weights[0] = (i, 1.0 - f);
+            weights[1] = (i + 1, f);
+        },
+        Interpolation::Bezier => {
+            // handles a third of the way along the tangents make the curve a cubic
+            // Hermite spline in time. The tangents follow the neighbours (Catmull-Rom) and
+            // are flat at the ends
+            let (f2, f3) = (f * f, f * f * f);
+            let h00 = 2.0 * f3 - 3.0 * f2 + 1.0;
+            let h10 = (f3 - 2.0 * f2 + f) * (t1 - t0);
+            let h01 = -2.0 * f3 + 3.0 * f2;
+            let h11 = (f3 - f2) * (t1 - t0);
+            weights[0] = (i, h00);
+            weights[1] = (i + 1, h01);
+            // tangent at i is (v[i + 1] - v[i - 1]) / (t[i + 1] - t[i - 1])
+            if i > 0 {
+                let m = h10 / (t1 - times[i - 1]);
+                weights[1].1 += m;
+                weights[2] = (i - 1, -m);
+            }
+            // tangent at i + 1 is (v[i + 2] - v[i]) / (t[i + 2] - t[i])
+            if i + 2 < times.len() {
+                let m = h11 / (times[i + 2] - t0);
+                weights[0].1 -= m;
+                weights[3] = (i + 2, m);
+            }
+        }
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpolate(times: &[f32], values: &[f32], interpolation: Interpolation, time: f32) -> f32 {
+        let interpolations = vec![interpolation; times.len()];
+        weights(times, &interpolations, time).iter().map(|&(i, w)| w * values[i]).sum()
+    }
+
+    #[test]
+    fn keyframes() {
+        let (times, values) = ([0.0, 1.0, 3.0], [2.0, 4.0, 0.0]);
+        for interpolation in [Interpolation::Linear, Interpolation::Bezier] {
+            // through the keyframes and held outside of them
+            for (time, value) in [(-1.0, 2.0), (0.0, 2.0), (1.0, 4.0), (3.0, 0.0), (5.0, 0.0)] {
+                let v = interpolate(&times, &values, interpolation, time);
+                assert!((v - value).abs() < 1e-5, "{:?} at {}: {} != {}", interpolation, time, v, value);
+            }
+        }
+        assert!((interpolate(&times, &values, Interpolation::Linear, 2.0) - 2.0).abs() < 1e-5);
+
+        // the bezier curve leaves the first keyframe flat and passes the second one with
+        // the slope between its neighbours
+        let slope = |time: f32| (interpolate(&times, &values, Interpolation::Bezier, time + 1e-3)
+            - interpolate(&times, &values, Interpolation::Bezier, time - 1e-3)) / 2e-3;
+        assert!(slope(1e-3).abs() < 0.05, "{}", slope(1e-3));
+        assert!((slope(1.0) - (0.0 - 2.0) / 3.0).abs() < 0.05, "{}", slope(1.0));
+        assert!(slope(3.0 - 1e-3).abs() < 0.05);
+    }
+}
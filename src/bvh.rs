@@ -0,0 +1,289 @@
+use crate::ray::{Ray, Intersection};
+use crate::vec3::Vec3;
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 0.125;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb{min, max}
+    }
+
+    pub fn empty() -> Aabb {
+        Aabb{min: Vec3::from(f32::INFINITY), max: Vec3::from(f32::NEG_INFINITY)}
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb{
+            min: Vec3{x: self.min.x.min(other.min.x), y: self.min.y.min(other.min.y), z: self.min.z.min(other.min.z)},
+            max: Vec3{x: self.max.x.max(other.max.x), y: self.max.y.max(other.max.y), z: self.max.z.max(other.max.z)},
+        }
+    }
+
+    pub fn grow(&self, p: &Vec3) -> Aabb {
+        self.union(&Aabb{min: *p, max: *p})
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 { return 0.0 }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // returns the distance at which the ray enters the box, if it does before t_max
+    pub fn hit(&self, origin: &Vec3, inv_direction: &Vec3, t_max: f32) -> Option<f32> {
+        self.span(origin, inv_direction, t_max).map(|(t_min, _)| t_min)
+    }
+
+    // distances at which the ray enters and leaves the box, limited to 0..t_max
+    pub fn span(&self, origin: &Vec3, inv_direction: &Vec3, t_max: f32) -> Option<(f32, f32)> {
+        if self.min.x > self.max.x { return None }
+        let mut t_min = 0.0f32;
+        let mut t_max = t_max;
+        for (o, inv, min, max) in [
+            (origin.x, inv_direction.x, self.min.x, self.max.x),
+            (origin.y, inv_direction.y, self.min.y, self.max.y),
+            (origin.z, inv_direction.z, self.min.z, self.max.z),
+        ] {
+            let t0 = (min - o) * inv;
+            let t1 = (max - o) * inv;
+            // NaN (0 * inf) compares false and keeps the previous bounds
+            let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+            if t0 > t_min { t_min = t0 }
+            if t1 < t_max { t_max = t1 }
+            if t_min > t_max { return None }
+        }
+        Some((t_min, t_max))
+    }
+
+    fn axis(v: &Vec3, axis: usize) -> f32 {
+        match axis {
+            0 => v.x,
+            1 => v.y,
+            _ => v.z,
+        }
+    }
+}
+
+enum Node {
+    Leaf{bounds: Aabb, first: usize, count: usize},
+    Inner{bounds: Aabb, left: usize, right: usize},
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf{bounds, ..} | Node::Inner{bounds, ..} => bounds
+        }
+    }
+}
+
+// bounding volume hierarchy over primitives identified by their index
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[(usize, Aabb)]) -> Bvh {
+        let mut bvh = Bvh{nodes: Vec::new(), indices: Vec::new()};
+        if !bounds.is_empty() {
+            let mut primitives = bounds.to_vec();
+            bvh.build(&mut primitives);
+        }
+        bvh
+    }
+
+    fn build(&mut self, primitives: &mut [(usize, Aabb)]) -> usize {
+        let bounds = primitives.iter().fold(Aabb::empty(), |b, (_, p)| b.union(p));
+        let node = self.nodes.len();
+
+        match Bvh::find_split(primitives, &bounds) {
+            Some(mid) => {
+                self.nodes.push(Node::Inner{bounds, left: 0, right: 0});
+                let (left_primitives, right_primitives) = primitives.split_at_mut(mid);
+                let left = self.build(left_primitives);
+                let right = self.build(right_primitives);
+                self.nodes[node] = Node::Inner{bounds, left, right};
+            },
+            None => {
+                let first = self.indices.len();
+                self.indices.extend(primitives.iter().map(|(i, _)| *i));
+                self.nodes.push(Node::Leaf{bounds, first, count: primitives.len()});
+            }
+        }
+        node
+    }
+
+    // binned surface area heuristic, partitions the primitives and returns the split position
+    fn find_split(primitives: &mut [(usize, Aabb)], bounds: &Aabb) -> Option<usize> {
+        let n = primitives.len();
+        if n <= 1 { return None }
+
+        let centroids = primitives.iter().fold(Aabb::empty(), |b, (_, p)| b.grow(&p.centroid()));
+        let mut best: Option<(f32, usize, usize)> = None;
+
+        for axis in 0..3 {
+            let min = Aabb::axis(&centroids.min, axis);
+            let extent = Aabb::axis(&centroids.max, axis) - min;
+            if extent <= 0.0 { continue }
+            let bin_of = |p: &Aabb| {
+                let b = ((Aabb::axis(&p.centroid(), axis) - min) / extent * SAH_BINS as f32) as usize;
+                usize::min(b, SAH_BINS - 1)
+            };
+
+            let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+            for (_, p) in primitives.iter() {
+                let bin = &mut bins[bin_of(p)];
+                bin.0 = bin.0.union(p);
+                bin.1 += 1;
+            }
+
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right = (Aabb::empty(), 0);
+            for i in (1..SAH_BINS).rev() {
+                right = (right.0.union(&bins[i].0), right.1 + bins[i].1);
+                right_area[i] = right.0.surface_area() * right.1 as f32;
+            }
+
+            let mut left = (Aabb::empty(), 0);
+            for i in 0..SAH_BINS - 1 {
+                left = (left.0.union(&bins[i].0), left.1 + bins[i].1);
+                let cost = left.0.surface_area() * left.1 as f32 + right_area[i + 1];
+                if left.1 > 0 && left.1 < n && best.is_none_or(|(c, ..)| cost < c) {
+                    best = Some((cost, axis, i));
+                }
+            }
+        }
+
+        let (cost, axis, split_bin) = best?;
+        let cost = TRAVERSAL_COST + cost / bounds.surface_area().max(f32::MIN_POSITIVE);
+        if n <= MAX_LEAF_SIZE && cost >= n as f32 { return None }
+
+        let min = Aabb::axis(&centroids.min, axis);
+        let extent = Aabb::axis(&centroids.max, axis) - min;
+        primitives.sort_unstable_by(|(_, a), (_, b)| {
+            Aabb::axis(&a.centroid(), axis).total_cmp(&Aabb::axis(&b.centroid(), axis))
+        });
+        let split_at = min + extent * (split_bin + 1) as f32 / SAH_BINS as f32;
+        let mid = primitives.partition_point(|(_, p)| Aabb::axis(&p.centroid(), axis) < split_at);
+        if mid == 0 || mid == n { Some(n / 2) } else { Some(mid) }
+    }
+
+    // returns the closest intersection found by `intersect` among the primitives the ray may hit
+    pub fn traverse<F>(&self, ray: &Ray, mut intersect: F) -> Option<(usize, Intersection)>
+    where F: FnMut(usize) -> Option<Intersection> {
+        if self.nodes.is_empty() { return None }
+
+        let direction = Vec3::from(ray.direction);
+        let inv_direction = Vec3{x: 1.0 / direction.x, y: 1.0 / direction.y, z: 1.0 / direction.z};
+        let mut closest: Option<(usize, Intersection)> = None;
+        let mut t_max = f32::INFINITY;
+
+        let mut stack = Vec::with_capacity(64);
+        if self.nodes[0].bounds().hit(&ray.origin, &inv_direction, t_max).is_some() {
+            stack.push(0);
+        }
+
+        while let Some(node) = stack.pop() {
+            match &self.nodes[node] {
+                Node::Leaf{bounds, first, count} => {
+                    if bounds.hit(&ray.origin, &inv_direction, t_max).is_none() { continue }
+                    for &idx in &self.indices[*first..*first + *count] {
+                        if let Some(intersection) = intersect(idx) {
+                            if intersection.t < t_max {
+                                t_max = intersection.t;
+                                closest = Some((idx, intersection));
+                            }
+                        }
+                    }
+                },
+                Node::Inner{bounds, left, right} => {
+                    if bounds.hit(&ray.origin, &inv_direction, t_max).is_none() { continue }
+                    let t_left = self.nodes[*left].bounds().hit(&ray.origin, &inv_direction, t_max);
+                    let t_right = self.nodes[*right].bounds().hit(&ray.origin, &inv_direction, t_max);
+                    // push the farther child first so the closer one is visited first
+                    match (t_left, t_right) {
+                        (Some(tl), Some(tr)) if tl <= tr => { stack.push(*right); stack.push(*left) },
+                        (Some(_), Some(_)) => { stack.push(*left); stack.push(*right) },
+                        (Some(_), None) => stack.push(*left),
+                        (None, Some(_)) => stack.push(*right),
+                        (None, None) => {},
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::Camera;
+    use crate::object::Object;
+    use crate::object::material::Interface;
+    use crate::object::mesh::{Mesh, Triangle, Vertex};
+    use crate::object::plane::Plane;
+    use crate::object::sphere::Sphere;
+    use crate::ray::Ray;
+    use crate::scene::Scene;
+    use crate::vec3::{Vec3, Vec3n};
+
+    use rand::prelude::*;
+
+    // spheres, rectangles and a triangle soup scattered through -10..10
+    fn objects() -> Vec<Box<dyn Object + Sync>> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut point = |size: f32| Vec3{x: rng.gen_range(-size..size), y: rng.gen_range(-size..size), z: rng.gen_range(-size..size)};
+        let mut objs: Vec<Box<dyn Object + Sync>> = Vec::new();
+        for i in 0..200 {
+            objs.push(Box::new(Sphere::new(point(10.0), 0.1 + (i % 10) as f32 * 0.15, Box::new(Interface))));
+        }
+        for i in 0..20 {
+            let (position, normal) = (point(10.0), Vec3n::from(point(1.0)));
+            objs.push(Box::new(Plane::new(position, normal, (0.5 + (i % 4) as f32, 4.0 - (i % 3) as f32), Box::new(Interface))));
+        }
+        let triangles = (0..300).map(|_| {
+            let a = point(10.0);
+            Triangle::new([a, a + point(1.0), a + point(1.0)].map(|pos| Vertex{pos, normal: None, uv: (0.0, 0.0)}))
+        }).collect();
+        objs.push(Box::new(Mesh::new(triangles, Box::new(Interface))));
+        objs
+    }
+
+    // the bvh finds the same closest hits as testing every object
+    #[test]
+    fn same_hits_as_linear() {
+        let camera = || Camera::new(90.0, &Vec3{x: 0.0, y: -20.0, z: 0.0}, &Vec3::default(), &Vec3n::new(0.0, 0.0, 1.0), 0.0);
+        let bvh = Scene::builder(camera()).objects(objects()).build();
+        let linear = Scene::builder(camera()).objects(objects()).disable_bvh().build();
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut hits = 0;
+        for _ in 0..20000 {
+            let origin = Vec3{x: rng.gen_range(-15.0..15.0), y: rng.gen_range(-15.0..15.0), z: rng.gen_range(-15.0..15.0)};
+            let direction = Vec3n::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let ray = Ray::new(origin, direction);
+            match (bvh.first_intersect(&ray), linear.first_intersect(&ray)) {
+                (Some((_, a)), Some((_, b))) => {
+                    hits += 1;
+                    assert!((a.t - b.t).abs() <= 1e-5 * a.t.max(1.0), "{} != {}", a.t, b.t);
+                },
+                (None, None) => (),
+                (a, b) => panic!("bvh hit {}, linear hit {}", a.is_some(), b.is_some()),
+            }
+        }
+        assert!(hits > 2000, "only {} hits", hits);
+    }
+}
@@ -3,31 +3,254 @@ use crate::image::{Image, Resolution};
 use crate::color::Color;
 use crate::scene::Scene;
 use crate::ray::Ray;
+use crate::mat4::Mat4;
+use crate::motion::Motion;
+use crate::random::{sample_rng, SampleRng};
 
-use indicatif::{ProgressStyle, ParallelProgressIterator};
+use indicatif::{ProgressBar, ProgressStyle, ParallelProgressIterator};
 use rayon::prelude::*;
 use rand::prelude::*;
 
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// samples every active pixel gets per pass of the progressive renderer
+const PASS_SAMPLES: u32 = 16;
+
+// settings of the progressive renderer, pixels are sampled until their relative
+// standard error drops below `threshold`, they reach `max_samples` or the time is up
+#[derive(Debug, Clone, Copy)]
+pub struct Progressive {
+    pub threshold: f32,
+    pub max_samples: Option<u32>,
+    pub time_limit: Option<Duration>,
+}
+
+// running mean and variance of the pixel luminance (Welford)
+#[derive(Debug, Clone, Copy, Default)]
+struct PixelEstimate {
+    samples: u32,
+    sum: Color,
+    mean: f32,
+    m2: f32,
+}
+
+impl PixelEstimate {
+    fn add(&mut self, c: &Color) {
+        let l = c.luminance();
+        self.samples += 1;
+        self.sum = self.sum + c;
+        let delta = l - self.mean;
+        self.mean += delta / self.samples as f32;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    fn relative_error(&self) -> f32 {
+        if self.samples < 2 { return f32::INFINITY }
+        let variance = self.m2 / (self.samples - 1) as f32;
+        (variance / self.samples as f32).sqrt() / (self.mean + 1e-3)
+    }
+
+    fn color(&self) -> Color {
+        if self.samples == 0 { Color::default() } else { self.sum / self.samples as f32 }
+    }
+}
+
+// how directions around the camera are mapped onto the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // pinhole or thin lens, the only one with depth of field
+    Perspective,
+    // parallel rays, the image covers what the perspective one shows at the focus distance
+    Orthographic,
+    // equidistant 180° circle, black outside of it
+    Fisheye,
+    // the full sphere, looking along +x with z up gives the layout of environment maps
+    Equirectangular,
+    // angles along the width, heights along the height
+    Cylindrical,
+    // keeps vertical lines straight in wide views, `distance` 0 is perspective and 1 the
+    // stereographic cylindrical projection
+    Panini{distance: f32},
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectionParseError(String);
+
+impl std::fmt::Display for ProjectionParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown projection `{}`, use perspective, orthographic, fisheye, equirectangular, cylindrical or panini", self.0)
+    }
+}
+
+impl FromStr for Projection {
+    type Err = ProjectionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "fisheye" => Ok(Projection::Fisheye),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            "cylindrical" => Ok(Projection::Cylindrical),
+            "panini" => Ok(Projection::Panini{distance: 1.0}),
+            _ => Err(ProjectionParseError(s.to_string()))
+        }
+    }
+}
+
+// a camera described like a real one. The lens and the sensor are measured in millimeters,
+// the scene in meters
+#[derive(Debug, Clone, Copy)]
+pub struct Lens {
+    pub focal_length: f32,
+    // 36 for full frame
+    pub sensor_width: f32,
+    pub f_stop: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Lens{focal_length: 50.0, sensor_width: 36.0, f_stop: 8.0}
+    }
+}
+
+impl Lens {
+    // horizontal field of view in degrees
+    pub fn fov(&self) -> f32 {
+        f32::to_degrees(2.0 * (self.sensor_width / (2.0 * self.focal_length)).atan())
+    }
+
+    // diameter of the entrance pupil in meters
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_stop / 1000.0
+    }
+
+    // exposure compensation in stops that exposes radiance in cd/m² like a camera with this
+    // f-stop, `shutter` seconds and `iso` would (saturation based sensitivity)
+    pub fn exposure(&self, iso: f32, shutter: f32) -> f32 {
+        let ev100 = (self.f_stop * self.f_stop / shutter).log2() - (iso / 100.0).log2();
+        -ev100 - 1.2f32.log2()
+    }
+}
+
+// uniform point on the unit disc, concentric mapping by Shirley and Chiu
+fn concentric_disc(u: f32, v: f32) -> (f32, f32) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 { return (0.0, 0.0) }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f32::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+// uniform point on a regular polygon with `corners` on the unit circle, the first one at
+// `rotation` radians
+fn polygon(corners: u32, rotation: f32, u: f32, v: f32) -> (f32, f32) {
+    // pick one of the triangles between the center and two neighbouring corners and
+    // reuse the rest of `u` inside it
+    let n = corners as f32;
+    let segment = (u * n).floor().min(n - 1.0);
+    let su = (u * n - segment).sqrt();
+    let corner = |i: f32| (rotation + std::f32::consts::TAU * i / n).sin_cos();
+    let ((s0, c0), (s1, c1)) = (corner(segment), corner(segment + 1.0));
+    let (a, b) = (su * (1.0 - v), su * v);
+    (a * c0 + b * c1, a * s0 + b * s1)
+}
+
 #[derive(Debug)]
 pub struct Camera {
     pos: Vec3,
-    plane_pos: Vec3,
+    forward: Vec3n,
     rigth: Vec3n, 
     up: Vec3n,
-    plane_half_size: f32,
+    // tangent of half the field of view
+    tan_half_fov: f32,
+    focus_distance: f32,
     aperture: f32,
+    // polygonal aperture, 0 for a round one
+    blades: u32,
+    blade_rotation: f32,
+    // stops added to the exposure of the tonemapper
+    exposure: f32,
+    projection: Projection,
+    // rays get a time between `time` and `time + shutter`
+    time: f32,
+    shutter: f32,
+    motion: Option<Motion>,
 }
 
 impl Camera {
+    // `fov` is horizontal, the camera is focused on `target`, `aperture` is the diameter of
+    // the lens, 0 for a pinhole
     pub fn new(fov: f32, eye: &Vec3, target: &Vec3, plane_up: &Vec3n, aperture: f32) -> Self {
         let pos = eye;
         let focus_distance = (target - eye).len();
-        let plane_pos = eye + (target - eye);
-        let forward = (plane_pos - pos).norm();
-        let plane_half_size = (f32::to_radians(fov) / 2.0).tan() * focus_distance;
+        let forward = (target - pos).norm();
+        let tan_half_fov = (f32::to_radians(fov) / 2.0).tan();
         let rigth = forward.cross(plane_up);
         let up = rigth.cross(&forward);
-        Camera {pos: *pos, plane_pos, rigth, up, plane_half_size, aperture}
+        Camera {pos: *pos, forward, rigth, up, tan_half_fov, focus_distance, aperture, blades: 0, blade_rotation: 0.0, exposure: 0.0, projection: Projection::Perspective,
+            time: 0.0, shutter: 0.0, motion: None}
+    }
+
+    // field of view and depth of field follow from the lens, exposure is set separately
+    pub fn physical(lens: &Lens, eye: &Vec3, target: &Vec3, plane_up: &Vec3n) -> Self {
+        Camera::new(lens.fov(), eye, target, plane_up, lens.aperture())
+    }
+
+    // focus somewhere else than on the target
+    pub fn with_focus_distance(self, focus_distance: f32) -> Self {
+        Camera{focus_distance, ..self}
+    }
+
+    // an aperture with straight blades gives polygonal bokeh, rotation in degrees
+    pub fn with_blades(self, blades: u32, rotation: f32) -> Self {
+        Camera{blades, blade_rotation: rotation.to_radians(), ..self}
+    }
+
+    // usually from `Lens::exposure`
+    pub fn with_exposure(self, exposure: f32) -> Self {
+        Camera{exposure, ..self}
+    }
+
+    pub fn with_projection(self, projection: Projection) -> Self {
+        Camera{projection, ..self}
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    // the shutter opens at `time` and stays open for `shutter`, moving objects are blurred
+    pub fn with_shutter(self, time: f32, shutter: f32) -> Self {
+        Camera{time, shutter, ..self}
+    }
+
+    // keyframes move and turn the camera around its eye
+    pub fn with_motion(self, motion: Motion) -> Self {
+        Camera{motion: Some(motion), ..self}
+    }
+
+    // exposure compensation in stops for the tonemapper
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    // how long the shutter stays open in seconds, moving objects are blurred over it
+    pub fn shutter(&self) -> f32 {
+        self.shutter
+    }
+
+    // offset from the center of the lens in the plane of the camera
+    fn sample_aperture(&self, rng: &mut SampleRng) -> Vec3 {
+        if self.aperture <= 0.0 { return Vec3::default() }
+        let (u, v) = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        let (x, y) = if self.blades >= 3 { polygon(self.blades, self.blade_rotation, u, v) } else { concentric_disc(u, v) };
+        let radius = self.aperture / 2.0;
+        radius * x * self.rigth + radius * y * self.up
     }
 
     pub fn take_picture(&self, resolution: Resolution, scene: &Scene, rays: u32) -> Image {
@@ -42,34 +265,187 @@ impl Camera {
         img
     }
 
+    // renders in passes and only spends further samples on pixels that have not converged
+    pub fn take_picture_progressive(&self, resolution: Resolution, scene: &Scene, settings: &Progressive) -> Image {
+        let start = Instant::now();
+        let mut estimates = vec![PixelEstimate::default(); resolution.width * resolution.height];
+        let progress = ProgressBar::new_spinner();
+        progress.set_style(ProgressStyle::with_template("[{elapsed}] pass {pos}: {msg}").unwrap());
+
+        loop {
+            let out_of_time = settings.time_limit.is_some_and(|limit| start.elapsed() >= limit);
+            let is_active = |e: &PixelEstimate| {
+                settings.max_samples.is_none_or(|max| e.samples < max) && e.relative_error() > settings.threshold
+            };
+            let active = estimates.iter().filter(|e| is_active(e)).count();
+            if active == 0 || out_of_time { break }
+
+            progress.set_message(format!("{} pixels active", active));
+            estimates.par_iter_mut().enumerate().filter(|(_, e)| is_active(e)).for_each(|(idx, estimate)| {
+                let (h,w) = resolution.get_height_width(idx);
+                let samples = settings.max_samples.map_or(PASS_SAMPLES, |max| u32::min(PASS_SAMPLES, max - estimate.samples));
+                let mut rng = sample_rng();
+                for _ in 0..samples {
+                    estimate.add(&self.sample_pixel(h as f32, w as f32, &resolution, scene, &mut rng));
+                }
+            });
+            progress.inc(1);
+        }
+
+        let total: u64 = estimates.iter().map(|e| e.samples as u64).sum();
+        progress.finish_with_message(format!("{:.1} samples per pixel on average", total as f32 / estimates.len() as f32));
+
+        let mut img = Image::new(resolution);
+        img.get_data_mut().iter_mut().zip(estimates.iter()).for_each(|(color, estimate)| *color = estimate.color());
+        img
+    }
+
     fn capture_pixel(&self, h: f32, w: f32, resolution: &Resolution,  scene: &Scene, rays: u32) -> Color {
 
-        let mut rng = thread_rng();
+        let mut rng = sample_rng();
 
 
         (0..rays).map(|_| {
-            let rand_x = rng.gen_range(0.0..1.0);
-            let rand_y = rng.gen_range(0.0..1.0);
-            let pos_on_plane = Vec3{
-                x: (w + rand_x - (resolution.width/2) as f32) / (resolution.width/2) as f32,
-                y: (h + rand_y - (resolution.height/2) as f32) / (resolution.height/2) as f32,
-                z: 0.0
-            };
+            self.sample_pixel(h, w, resolution, scene, &mut rng)
+        }).fold(Color::default(), |a,b| { a + b }) / rays as f32
+    }
 
-            let blur_offset = Vec3n::random_disc() * (self.aperture / 2.0);
-            let blur_offset = self.rigth * blur_offset.x + self.up * blur_offset.y;
+    fn sample_pixel(&self, h: f32, w: f32, resolution: &Resolution, scene: &Scene, rng: &mut SampleRng) -> Color {
+        let rand_x = rng.gen_range(0.0..1.0);
+        let rand_y = rng.gen_range(0.0..1.0);
+        // x from -1 at the left to 1 at the right edge, y up in the same units
+        let x = 2.0 * (w + rand_x) / resolution.width as f32 - 1.0;
+        let y = (1.0 - 2.0 * (h + rand_y) / resolution.height as f32) / resolution.aspect();
 
-            let plane_intersection =
-                self.plane_pos +
-                self.plane_half_size * pos_on_plane.x * self.rigth +
-                self.plane_half_size * pos_on_plane.y * self.up;
+        let time = self.time + rng.gen_range(0.0..1.0) * self.shutter;
+        match self.ray(x, y, resolution.aspect(), rng) {
+            Some(ray) => scene.trace(&self.moved(ray, time)).fix(),
+            None => Color::default()
+        }
+    }
 
-            let ray = Ray {
-                origin: self.pos + blur_offset,
-                direction: Vec3n::from(plane_intersection  - self.pos - blur_offset),
-                inside: false
-            };
-            scene.trace(&ray, 0).fix()
-        }).fold(Color::default(), |a,b| { a + b }) / rays as f32
+    fn moved(&self, ray: Ray, time: f32) -> Ray {
+        let Some(motion) = &self.motion else { return Ray{time, ..ray} };
+        let transform = Mat4::translation(&self.pos) * motion.at(time) * Mat4::translation(&(Vec3::default() - self.pos));
+        let direction = Vec3n::from(transform.transform_vector(&Vec3::from(ray.direction)));
+        Ray{origin: transform.transform_point(&ray.origin), direction, time, ..ray}
+    }
+
+    // ray through the point of the image at `x` from -1 to 1 and `y` from -1 / aspect to
+    // 1 / aspect, None outside of the fisheye
+    fn ray(&self, x: f32, y: f32, aspect: f32, rng: &mut SampleRng) -> Option<Ray> {
+        let half_fov = self.tan_half_fov.atan();
+        // direction from angles around the up axis and heights along it
+        let around = |angle: f32, height: f32| {
+            Vec3n::from(angle.cos() * self.forward + angle.sin() * self.rigth + height * self.up)
+        };
+        let ray = |origin: Vec3, direction: Vec3n| Some(Ray::new(origin, direction));
+
+        match self.projection {
+            Projection::Perspective => {
+                let blur_offset = self.sample_aperture(rng);
+                // everything on the plane at the focus distance is sharp
+                let plane_half_size = self.tan_half_fov * self.focus_distance;
+                let plane_intersection =
+                    self.pos + self.focus_distance * self.forward +
+                    plane_half_size * x * self.rigth +
+                    plane_half_size * y * self.up;
+                ray(self.pos + blur_offset, Vec3n::from(plane_intersection - self.pos - blur_offset))
+            },
+            Projection::Orthographic => {
+                let half_size = self.tan_half_fov * self.focus_distance;
+                ray(self.pos + half_size * x * self.rigth + half_size * y * self.up, self.forward)
+            },
+            Projection::Fisheye => {
+                // the circle fits into the shorter side
+                let r = (x * x + y * y).sqrt() * aspect.max(1.0);
+                if r > 1.0 { return None }
+                let theta = r * std::f32::consts::FRAC_PI_2;
+                let (sin, cos) = theta.sin_cos();
+                let length = (x * x + y * y).sqrt();
+                let (dx, dy) = if length > 0.0 { (x / length, y / length) } else { (0.0, 0.0) };
+                ray(self.pos, Vec3n::from(cos * self.forward + sin * dx * self.rigth + sin * dy * self.up))
+            },
+            Projection::Equirectangular => {
+                // the full sphere whatever the aspect, 2:1 images are not distorted
+                let (longitude, latitude) = (x * std::f32::consts::PI, y * aspect * std::f32::consts::FRAC_PI_2);
+                let (sin, cos) = latitude.sin_cos();
+                ray(self.pos, Vec3n::from(cos * Vec3::from(around(longitude, 0.0)) + sin * self.up))
+            },
+            Projection::Cylindrical => {
+                // the same scale in both directions at the center of the image
+                ray(self.pos, around(x * half_fov, y * half_fov))
+            },
+            Projection::Panini{distance: d} => {
+                // scaled so the edges of the image are at half the field of view
+                let scale = (d + 1.0) * half_fov.sin() / (d + half_fov.cos());
+                let (x, y) = (x * scale, y * scale);
+                // inverse of x = s sin(angle), y = s height with s = (d + 1) / (d + cos(angle))
+                let k = x * x / ((d + 1.0) * (d + 1.0));
+                let discriminant = (k * k * d * d - (k + 1.0) * (k * d * d - 1.0)).max(0.0);
+                let cos = (-k * d + discriminant.sqrt()) / (k + 1.0);
+                let s = (d + 1.0) / (d + cos);
+                ray(self.pos, around(x.atan2(s * cos), y / s))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lens() {
+        let lens = Lens::default();
+        assert!((lens.fov() - 39.6).abs() < 0.1);
+        assert!((lens.aperture() - 0.00625).abs() < 1e-6);
+        // sunny 16: f/16 at 1/100s and ISO 100 is about 15 stops darker than f/1 at 1s
+        let sunny = Lens{f_stop: 16.0, ..lens}.exposure(100.0, 0.01);
+        assert!((sunny + 14.9).abs() < 0.1, "{}", sunny);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn projections() {
+        let mut rng = sample_rng();
+        let camera = |projection| Camera::new(100.0, &Vec3::default(), &Vec3{x: 1.0, y: 0.0, z: 0.0}, &Vec3n::new(0.0, 0.0, 1.0), 0.0)
+            .with_projection(projection);
+        let direction = |camera: &Camera, x, y| Vec3::from(camera.ray(x, y, 1.0, &mut sample_rng()).unwrap().direction);
+        let assert_close = |a: Vec3, b: Vec3| assert!((a - b).len() < 1e-5, "{:?} != {:?}", a, b);
+
+        // panini without distance is the perspective projection
+        let (perspective, panini) = (camera(Projection::Perspective), camera(Projection::Panini{distance: 0.0}));
+        for (x, y) in [(0.0, 0.0), (0.7, -0.3), (-1.0, 1.0)] {
+            assert_close(direction(&perspective, x, y), direction(&panini, x, y));
+        }
+
+        // the panorama is laid out like environment maps, +x in the center and -y right of it
+        let panorama = camera(Projection::Equirectangular);
+        assert_close(direction(&panorama, 0.0, 0.0), Vec3{x: 1.0, y: 0.0, z: 0.0});
+        assert_close(direction(&panorama, 0.5, 0.0), Vec3{x: 0.0, y: -1.0, z: 0.0});
+        assert_close(direction(&panorama, 0.0, 0.5), Vec3{x: f32::sqrt(0.5), y: 0.0, z: f32::sqrt(0.5)});
+        // 2:1 images cover the sphere without distortion
+        let up = Vec3::from(panorama.ray(0.0, 0.5, 2.0, &mut rng).unwrap().direction);
+        assert_close(up, Vec3{x: 0.0, y: 0.0, z: 1.0});
+
+        let fisheye = camera(Projection::Fisheye);
+        assert_close(direction(&fisheye, -1.0, 0.0), Vec3{x: 0.0, y: 1.0, z: 0.0});
+        assert!(fisheye.ray(0.8, 0.8, 1.0, &mut rng).is_none());
+    }
+
+    // samples cover the polygon of the blades evenly and stay inside it
+    #[test]
+    fn polygonal_aperture() {
+        let mut rng = sample_rng();
+        let (corners, rotation) = (6, 0.3);
+        let n = 100_000;
+        let samples: Vec<_> = (0..n).map(|_| polygon(corners, rotation, rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0))).collect();
+        // the inner circle of a hexagon touches the middle of its edges
+        let inner = f32::cos(std::f32::consts::PI / 6.0);
+        assert!(samples.iter().all(|(x, y)| x * x + y * y <= 1.0 + 1e-5));
+        let inside = samples.iter().filter(|(x, y)| x * x + y * y <= inner * inner).count() as f32 / n as f32;
+        // ratio of the areas of the circle and the hexagon
+        let expected = std::f32::consts::PI * inner * inner / (1.5 * 3f32.sqrt());
+        assert!((inside - expected).abs() < 0.01, "{} != {}", inside, expected);
+    }
+}
@@ -8,31 +8,36 @@ pub struct Color {
 }
 
 #[derive(Debug, Clone)]
-pub struct HexParseError;
+pub struct HexParseError {
+    pub hex: String,
+    pub reason: String,
+}
 
-impl From<std::num::ParseIntError> for HexParseError{
-    fn from(_: std::num::ParseIntError) -> Self { HexParseError }
+impl std::fmt::Display for HexParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid hex color `{}`: {}", self.hex, self.reason)
+    }
 }
 
+impl std::error::Error for HexParseError {}
+
 impl Color {
     pub fn new(a: f32) -> Color {
         Color{r: a, g: a, b: a}
     }
 
+    // `#RRGGBB`
     pub fn from_hex(hex: &str) -> Result<Color, HexParseError> {
-        if hex.starts_with("#") {
-            let r = hex.get(1..3).ok_or(HexParseError)?;
-            let g = hex.get(3..5).ok_or(HexParseError)?;
-            let b = hex.get(5..7).ok_or(HexParseError)?;
-
-
-            let r =  u8::from_str_radix(r, 16)? as f32 / 255.;
-            let g =  u8::from_str_radix(g, 16)? as f32 / 255.;
-            let b =  u8::from_str_radix(b, 16)? as f32 / 255.;
-            Ok(Color{r, g, b})
-        } else {
-            Err(HexParseError)
+        let error = |reason: String| HexParseError{hex: hex.to_string(), reason};
+        let digits = hex.strip_prefix('#').ok_or_else(|| error("missing leading `#`".to_string()))?;
+        if digits.len() != 6 {
+            return Err(error(format!("expected 6 hex digits, found {}", digits.len())))
         }
+        let channel = |i: usize| {
+            let digits = digits.get(i..i + 2).ok_or_else(|| error("not ascii".to_string()))?;
+            u8::from_str_radix(digits, 16).map(|c| c as f32 / 255.).map_err(|e| error(e.to_string()))
+        };
+        Ok(Color{r: channel(0)?, g: channel(2)?, b: channel(4)?})
     }
 
     pub fn pow(&self, exp: f32) -> Color {
@@ -43,6 +48,15 @@ impl Color {
         }
     }
 
+    pub fn map(&self, f: impl Fn(f32) -> f32) -> Color {
+        Color{r: f(self.r), g: f(self.g), b: f(self.b)}
+    }
+
+    // relative luminance of linear sRGB
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
     pub fn fix(&self) -> Self {
         if f32::is_nan(self.r) || f32::is_nan(self.g) || f32::is_nan(self.b) {
             Color::default()
@@ -0,0 +1,71 @@
+// piecewise constant distribution over [0, 1) proportional to `func`
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in func.iter() {
+            cdf.push(cdf.last().unwrap() + f.max(0.0) / n);
+        }
+        let integral = *cdf.last().unwrap();
+        if integral > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        }
+        Distribution1D{func, cdf, integral}
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    // returns the sampled position, its pdf and the index of the segment it lies in
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.func.len() - 1);
+        let segment = self.cdf[i + 1] - self.cdf[i];
+        let offset = if segment > 0.0 { (u - self.cdf[i]) / segment } else { 0.0 };
+        let x = ((i as f32 + offset) / self.func.len() as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf(x), i)
+    }
+
+    pub fn pdf(&self, x: f32) -> f32 {
+        if self.integral <= 0.0 { return 0.0 }
+        let i = ((x * self.func.len() as f32) as usize).min(self.func.len() - 1);
+        self.func[i].max(0.0) / self.integral
+    }
+}
+
+// piecewise constant distribution over [0, 1)², `func` is given row by row
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<_> = func.chunks(width).take(height).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(rows.iter().map(|r| r.integral()).collect());
+        Distribution2D{rows, marginal}
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    // returns (u, v) and the pdf with respect to the area of the unit square
+    pub fn sample(&self, u: f32, v: f32) -> ((f32, f32), f32) {
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.rows[row].sample(u);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
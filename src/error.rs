@@ -0,0 +1,52 @@
+use crate::scene_file::ParseError;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    // a file could not be opened, read or written
+    Io{path: PathBuf, source: io::Error},
+    // a file was read but does not contain a valid image
+    Image{path: PathBuf, reason: String},
+    // the output extension is not one of the formats `Image::save` writes
+    UnsupportedFormat{path: PathBuf},
+    Parse(ParseError),
+    Obj{path: PathBuf, source: tobj::LoadError},
+}
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io{path: path.into(), source}
+    }
+}
+
+// variants with a source only name what failed and leave the why to `source()`, parse
+// errors are shown as they are
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io{path, ..} => write!(f, "i/o error on `{}`", path.display()),
+            Error::Image{path, reason} => write!(f, "`{}` is not a valid image: {}", path.display(), reason),
+            Error::UnsupportedFormat{path} => write!(f, "unsupported output format `{}`, use ppm, png, hdr, pfm or exr", path.display()),
+            Error::Parse(e) => e.fmt(f),
+            Error::Obj{path, ..} => write!(f, "could not load `{}`", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io{source, ..} => Some(source),
+            Error::Parse(e) => e.source(),
+            Error::Obj{source, ..} => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self { Error::Parse(e) }
+}
@@ -1,39 +1,85 @@
 use crate::color::Color;
+use crate::error::Error;
+
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+// how lookups outside of [0, 1] are mapped back onto the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    fn index(&self, i: isize, size: usize) -> usize {
+        let size = size as isize;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size { i } else { 2 * size - 1 - i }
+            }
+        };
+        i as usize
+    }
+}
 
 pub struct Image{
     pub resolution: Resolution,
     data: Vec<Color>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Resolution {
     pub width: usize,
     pub height: usize,
 }
 
-pub struct ImageError {}
-
-impl From<std::io::Error> for ImageError {   
-    fn from(_: std::io::Error) -> Self { ImageError {} }
-}
-
-impl From<hdrldr::LoadError> for ImageError {   
-    fn from(_: hdrldr::LoadError) -> Self { ImageError {} }
-}
-
 impl Resolution {
     pub fn new(width: usize, height: usize) -> Resolution {
         Resolution{width, height}
     }
 
+    // width divided by height
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    // row and column of a pixel, pixels are stored row by row starting at the top left
     pub fn get_height_width(&self, i: usize) -> (usize, usize) {
-        (self.width - i/self.width,i%self.width)
+        (i / self.width, i % self.width)
     }
 
     pub fn get_index(&self, width: usize, height: usize) -> usize {
-        usize::min(height * self.width + width, (self.width * self.height) -1 )
+        height * self.width + width
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolutionParseError(String);
+
+impl std::fmt::Display for ResolutionParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid resolution `{}`, use a size like 600 or 1920x1080", self.0)
+    }
+}
+
+// `600` for square images or `1920x1080`
+impl FromStr for Resolution {
+    type Err = ResolutionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let size = |v: &str| v.trim().parse::<usize>().ok().filter(|&v| v > 0);
+        let resolution = match s.split_once('x') {
+            Some((width, height)) => size(width).zip(size(height)),
+            None => size(s).map(|size| (size, size))
+        };
+        resolution.map(|(width, height)| Resolution{width, height}).ok_or_else(|| ResolutionParseError(s.to_string()))
     }
 }
 
@@ -53,8 +99,33 @@ impl Image {
         &mut self.data
     }
 
-    pub fn load(filename: &PathBuf) -> Result<Image, ImageError> {
-        let hdr = hdrldr::load(File::open(filename)?)?;
+    // loads a Radiance HDR image (linear) or a PNG (sRGB encoded), chosen by the extension
+    pub fn load(filename: &Path) -> Result<Image, Error> {
+        let extension = filename.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "png" => Image::load_png(filename, true),
+            _ => Image::load_hdr(filename),
+        }
+    }
+
+    // like `load`, but PNG values are used as they are, e.g. for normal maps
+    pub fn load_linear(filename: &Path) -> Result<Image, Error> {
+        let extension = filename.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "png" => Image::load_png(filename, false),
+            _ => Image::load_hdr(filename),
+        }
+    }
+
+    fn load_hdr(filename: &Path) -> Result<Image, Error> {
+        let file = File::open(filename).map_err(|e| Error::io(filename, e))?;
+        let hdr = hdrldr::load(file).map_err(|e| match e {
+            hdrldr::LoadError::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                Error::Image{path: filename.to_path_buf(), reason: "truncated file".to_string()},
+            hdrldr::LoadError::Io(e) => Error::io(filename, e),
+            hdrldr::LoadError::FileFormat => Error::Image{path: filename.to_path_buf(), reason: "not a Radiance HDR file".to_string()},
+            hdrldr::LoadError::Rle => Error::Image{path: filename.to_path_buf(), reason: "corrupt run length encoding".to_string()},
+        })?;
         let resolution = Resolution{width: hdr.width, height: hdr.height};
         let mut image = Image::new(resolution);
         hdr.data.iter().enumerate().for_each(|(i, p)| {
@@ -62,4 +133,94 @@ impl Image {
         });
         Ok(image)
     }
-}
\ No newline at end of file
+
+    fn load_png(filename: &Path, srgb: bool) -> Result<Image, Error> {
+        let file = File::open(filename).map_err(|e| Error::io(filename, e))?;
+        let invalid = |e: png::DecodingError| match e {
+            png::DecodingError::IoError(e) => Error::io(filename, e),
+            e => Error::Image{path: filename.to_path_buf(), reason: e.to_string()},
+        };
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(invalid)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(invalid)?;
+        let channels = info.color_type.samples();
+
+        let mut image = Image::new(Resolution{width: info.width as usize, height: info.height as usize});
+        let linear = |v: u8| {
+            let v = v as f32 / 255.0;
+            if !srgb {
+                v
+            } else if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+        };
+        image.data.iter_mut().zip(buffer[..info.buffer_size()].chunks_exact(channels)).for_each(|(color, p)| {
+            // grey and grey with alpha have a single color channel
+            *color = if channels < 3 { Color::new(linear(p[0])) } else { Color{r: linear(p[0]), g: linear(p[1]), b: linear(p[2])} }
+        });
+        Ok(image)
+    }
+
+    // bilinear lookup with (0, 0) at the top left corner
+    pub fn bilinear(&self, u: f32, v: f32, wrap_u: Wrap, wrap_v: Wrap) -> Color {
+        let Resolution{width, height} = self.resolution;
+        let x = u * width as f32 - 0.5;
+        let y = v * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let (x0, x1) = (wrap_u.index(x0, width), wrap_u.index(x0 + 1, width));
+        let (y0, y1) = (wrap_v.index(y0, height), wrap_v.index(y0 + 1, height));
+        let at = |x: usize, y: usize| self.data[self.resolution.get_index(x, y)];
+        (at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx) * (1.0 - fy) + (at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx) * fy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_addressing() {
+        for (width, height) in [(1, 1), (4, 4), (7, 3), (2, 9), (1920, 1080)] {
+            let resolution = Resolution::new(width, height);
+            for i in 0..width * height {
+                let (row, column) = resolution.get_height_width(i);
+                assert!(row < height && column < width);
+                assert_eq!(resolution.get_index(column, row), i);
+            }
+        }
+        // rows are stored from the top
+        let resolution = Resolution::new(7, 3);
+        assert_eq!(resolution.get_height_width(0), (0, 0));
+        assert_eq!(resolution.get_height_width(6), (0, 6));
+        assert_eq!(resolution.get_height_width(7), (1, 0));
+        assert_eq!(resolution.get_height_width(20), (2, 6));
+    }
+
+    #[test]
+    fn wrap_modes() {
+        for (i, repeat, clamp, mirror) in [(-5, 3, 0, 3), (-1, 3, 0, 0), (0, 0, 0, 0), (3, 3, 3, 3), (4, 0, 3, 3), (5, 1, 3, 2), (8, 0, 3, 0), (9, 1, 3, 1)] {
+            assert_eq!(Wrap::Repeat.index(i, 4), repeat, "repeat {}", i);
+            assert_eq!(Wrap::Clamp.index(i, 4), clamp, "clamp {}", i);
+            assert_eq!(Wrap::Mirror.index(i, 4), mirror, "mirror {}", i);
+        }
+
+        // past the edge of a black and a white pixel
+        let mut image = Image::new(Resolution::new(2, 1));
+        image.get_data_mut()[1] = Color::new(1.0);
+        assert_eq!(image.bilinear(0.0, 0.5, Wrap::Repeat, Wrap::Repeat).r, 0.5);
+        assert_eq!(image.bilinear(0.0, 0.5, Wrap::Clamp, Wrap::Clamp).r, 0.0);
+        assert_eq!(image.bilinear(1.0, 0.5, Wrap::Mirror, Wrap::Mirror).r, 1.0);
+        assert_eq!(image.bilinear(0.5, 0.5, Wrap::Clamp, Wrap::Clamp).r, 0.5);
+    }
+
+    #[test]
+    fn parse_resolution() {
+        assert_eq!("600".parse::<Resolution>().unwrap(), Resolution::new(600, 600));
+        assert_eq!("1920x1080".parse::<Resolution>().unwrap(), Resolution::new(1920, 1080));
+        for invalid in ["", "0", "1920x", "x1080", "1920x0", "-5", "12.5", "1920*1080"] {
+            assert!(invalid.parse::<Resolution>().is_err(), "{}", invalid);
+        }
+    }
+}
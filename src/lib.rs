@@ -0,0 +1,75 @@
+//! A small path tracer.
+//!
+//! Scenes are either loaded from a scene file with [`SceneFile::load`], which also keeps the
+//! resolution and samples the file asks for:
+//!
+//! ```no_run
+//! use rayt::{SceneFile, Resolution, Tonemap};
+//!
+//! let file = SceneFile::load("scenes/default.scene".as_ref()).unwrap();
+//! let image = file.scene.render(file.resolution.unwrap_or(Resolution::new(600, 600)), file.samples.unwrap_or(128));
+//! image.save("default.png".as_ref(), &Tonemap::default()).unwrap();
+//! ```
+//!
+//! or assembled in code:
+//!
+//! ```no_run
+//! use rayt::{Scene, Camera, Color, Vec3, Vec3n, Resolution, Tonemap};
+//! use rayt::object::sphere::Sphere;
+//! use rayt::object::material::Metalic;
+//!
+//! let camera = Camera::new(40.0, &Vec3{x: 0.0, y: -5.0, z: 1.0}, &Vec3::default(), &Vec3n::new(0.0, 0.0, 1.0), 0.0);
+//! let scene = Scene::builder(camera)
+//!     .object(Sphere::new(Vec3::default(), 1.0, Box::new(Metalic::gold())))
+//!     .build();
+//! let image = scene.render(Resolution::new(256, 256), 64);
+//! let center: Color = image.get_data()[128 * 256 + 128];
+//! image.save("gold.png".as_ref(), &Tonemap::default()).unwrap();
+//! ```
+
+#[macro_use] extern crate impl_ops;
+
+pub mod color;
+pub mod vec3;
+pub mod mat4;
+pub mod motion;
+pub mod animation;
+pub mod camera;
+pub mod ray;
+pub mod scene;
+pub mod scene_file;
+pub mod obj_file;
+pub mod object;
+pub mod image;
+pub mod tonemap;
+pub mod bvh;
+pub mod error;
+pub mod distribution;
+pub mod texture;
+pub mod spectrum;
+pub mod medium;
+mod render;
+mod random;
+
+pub use error::Error;
+pub use color::Color;
+pub use vec3::{Vec3, Vec3n};
+pub use mat4::Mat4;
+pub use motion::{Motion, Keyframe};
+pub use animation::Interpolation;
+pub use ray::{Ray, Intersection};
+pub use camera::{Camera, Lens, Progressive, Projection};
+pub use image::{Image, Resolution};
+pub use scene::{Scene, SceneBuilder, PathSettings};
+pub use scene_file::SceneFile;
+pub use object::{Object, Intersect, Shade, BsdfSample, Lobe};
+pub use object::light::{AmbientLight, Emissive};
+pub use object::material::{Diffuse, Metalic, Refractive, Plastic, Interface};
+#[allow(deprecated)]
+pub use object::material::DiffuseSpecular;
+pub use object::principled::Principled;
+pub use object::instance::{Instance, InstanceError};
+pub use tonemap::{Tonemap, Tonemapper};
+pub use texture::{Texture, ImageTexture, Checker, Noise};
+pub use spectrum::{Ior, Conductor};
+pub use medium::{Medium, Homogeneous, Heterogeneous, HenyeyGreenstein};
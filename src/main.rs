@@ -1,35 +1,293 @@
-mod color;
-mod vec3;
-mod render;
-mod camera;
-mod ray;
-mod scene;
-mod object;
-mod image;
+use rayt::{SceneFile, PathSettings, AmbientLight, Tonemap, Tonemapper, Progressive, Projection, Resolution, Error};
 
-#[macro_use] extern crate impl_ops;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use structopt::StructOpt;
 
-use scene::Scene;
+// accepts `90`, `90s`, `500ms`, `2m` or `1h`
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.parse().map_err(|_| format!("invalid duration `{}`", s))?;
+    let seconds = match unit {
+        "" | "s" => value,
+        "ms" => value / 1000.0,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("invalid duration unit `{}`, use ms, s, m or h", unit))
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration `{}`", s))
+}
+
+fn parse_size(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!("invalid size `{}`", s))
+    }
+}
+
+fn parse_rate(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("invalid rate `{}`, it has to be a positive number", s))
+    }
+}
+
+// frames of an animation, both ends included
+#[derive(Debug, Clone, Copy)]
+struct Frames {
+    first: u32,
+    last: u32,
+}
+
+// accepts `120` for the frames 1 to 120 or a range like `1..120`
+fn parse_frames(s: &str) -> Result<Frames, String> {
+    let invalid = || format!("invalid frames `{}`, use 120 or 1..120", s);
+    let (first, last) = match s.split_once("..") {
+        Some((first, last)) => (first.parse().map_err(|_| invalid())?, last.parse().map_err(|_| invalid())?),
+        None => (1, s.parse().map_err(|_| invalid())?)
+    };
+    if first > last {
+        return Err(invalid())
+    }
+    Ok(Frames{first, last})
+}
+
+// `img.png` becomes `img_0001.png` for frame 1
+fn frame_path(path: &Path, frame: u32, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}_{:04}{}", stem, frame, suffix);
+    if let Some(extension) = path.extension() {
+        name = format!("{}.{}", name, extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
 
-use std::path::PathBuf;
-use structopt::StructOpt;
 #[derive(Debug, StructOpt)]
 struct Opt {
-    #[structopt(long, parse(from_os_str), default_value = "")]
-    hdr_path: PathBuf,
+    /// Scene description to render, the built-in scene is used if omitted
+    #[structopt(long, parse(from_os_str))]
+    scene: Option<PathBuf>,
+
+    /// Equirectangular environment map, replaces the environment of the scene
+    #[structopt(long, parse(from_os_str))]
+    hdr_path: Option<PathBuf>,
 
+    /// Rotation of the --hdr-path environment around the up (z) axis in degrees
+    #[structopt(long, default_value = "0.0", allow_hyphen_values = true)]
+    hdr_rotation: f32,
+
+    /// Radiance multiplier of the --hdr-path environment
+    #[structopt(long, default_value = "1.0")]
+    hdr_intensity: f32,
+
+    /// Output image, the format follows the extension: ppm, png (tonemapped), hdr, pfm or exr (linear)
     #[structopt(short, long, parse(from_os_str), default_value = "img.ppm")]
     out_path: PathBuf,
 
-    #[structopt(short, long, default_value = "600")]
-    resolution: usize,
+    /// Overrides the resolution of the scene file, 600 or 1920x1080 [default: 600]
+    #[structopt(short, long)]
+    resolution: Option<Resolution>,
+
+    /// Overrides the width of the image
+    #[structopt(long, parse(try_from_str = parse_size))]
+    width: Option<usize>,
+
+    /// Overrides the height of the image
+    #[structopt(long, parse(try_from_str = parse_size))]
+    height: Option<usize>,
+
+    /// Overrides the samples of the scene file [default: 128], the maximum per pixel in progressive mode
+    #[structopt(short, long)]
+    samples: Option<u32>,
+
+    /// Render progressively and stop sampling pixels once their relative standard error is below this
+    #[structopt(long)]
+    threshold: Option<f32>,
+
+    /// Render progressively until the time is up (e.g. 60s, 5m), instead of a fixed number of samples
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    time_limit: Option<Duration>,
+
+    /// Bounces before russian roulette may terminate a path
+    #[structopt(long, default_value = "3")]
+    min_depth: u32,
 
-    #[structopt(short, long, default_value = "128")]
-    samples: u32,
+    /// Maximum number of bounces of a path
+    #[structopt(long, default_value = "64")]
+    max_depth: u32,
+
+    /// Maximum number of diffuse bounces of a path
+    #[structopt(long, default_value = "8")]
+    max_diffuse_depth: u32,
+
+    /// Maximum number of specular reflections of a path
+    #[structopt(long, default_value = "32")]
+    max_specular_depth: u32,
+
+    /// Maximum number of refractions of a path
+    #[structopt(long, default_value = "32")]
+    max_transmission_depth: u32,
+
+    /// Maximum number of scattering events in media of a path
+    #[structopt(long, default_value = "32")]
+    max_volume_depth: u32,
+
+    /// Overrides the camera projection: perspective, orthographic, fisheye, equirectangular, cylindrical or panini
+    #[structopt(long)]
+    projection: Option<Projection>,
+
+    /// Test every object for every ray instead of using the bounding volume hierarchy
+    #[structopt(long)]
+    no_bvh: bool,
+
+    /// Trace wavelengths instead of rgb, for dispersion and measured metals
+    #[structopt(long)]
+    spectral: bool,
+
+    /// Tonemapping operator for png and ppm output: clamp, reinhard, reinhard-extended, aces or hejl.
+    /// reinhard-extended:<white> sets the luminance that maps to white, 4 by default
+    #[structopt(long, default_value = "hejl")]
+    tonemap: Tonemapper,
+
+    /// Exposure compensation in stops (EV)
+    #[structopt(long, default_value = "0.0", allow_hyphen_values = true)]
+    exposure: f32,
+
+    /// Scale the exposure so the log-average luminance maps to middle grey
+    #[structopt(long)]
+    auto_exposure: bool,
+
+    /// Render the frames of an animation (e.g. 1..120) to numbered images, frames that already exist are skipped
+    #[structopt(long, parse(try_from_str = parse_frames))]
+    frames: Option<Frames>,
+
+    /// Frames per second of the animation, frame n is rendered at n / fps seconds
+    #[structopt(long, default_value = "24", parse(try_from_str = parse_rate))]
+    fps: f32,
 }
 
 fn main() {
-    let opt = Opt::from_args();
-    let scene = Scene::new(opt.samples, &opt.hdr_path);
-    scene.render(image::Resolution::new(opt.resolution, opt.resolution)).save(&opt.out_path).unwrap();
+    if let Err(e) = run(&Opt::from_args()) {
+        // the causes follow on the same line
+        let mut message = e.to_string();
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            message = format!("{}: {}", message, cause);
+            source = cause.source();
+        }
+        eprintln!("error: {}", message);
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: &Opt) -> Result<(), Error> {
+    let frames = match opt.frames {
+        Some(frames) => frames,
+        None => return render(opt, 0.0, &opt.out_path)
+    };
+    for frame in frames.first..=frames.last {
+        let out_path = frame_path(&opt.out_path, frame, "");
+        if out_path.exists() {
+            println!("skipping frame {}, {} exists", frame, out_path.display());
+            continue
+        }
+        println!("rendering frame {} of {}..{}", frame, frames.first, frames.last);
+        // an interrupted frame never looks finished
+        let partial = frame_path(&opt.out_path, frame, ".partial");
+        render(opt, frame as f32 / opt.fps, &partial)?;
+        std::fs::rename(&partial, &out_path).map_err(|e| Error::io(&out_path, e))?;
+    }
+    Ok(())
+}
+
+// renders the scene at `time` in seconds
+fn render(opt: &Opt, time: f32, out_path: &Path) -> Result<(), Error> {
+    let scene_file = match &opt.scene {
+        Some(path) => SceneFile::load_at(path, time)?,
+        None => SceneFile::default()
+    };
+    let mut scene = scene_file.scene;
+    if let Some(hdr_path) = &opt.hdr_path {
+        scene.set_ambient_light(AmbientLight::load(hdr_path)?.with_rotation(opt.hdr_rotation).with_intensity(opt.hdr_intensity));
+    }
+    scene.set_path_settings(PathSettings{
+        min_depth: opt.min_depth,
+        max_depth: opt.max_depth,
+        max_diffuse_depth: opt.max_diffuse_depth,
+        max_specular_depth: opt.max_specular_depth,
+        max_transmission_depth: opt.max_transmission_depth,
+        max_volume_depth: opt.max_volume_depth,
+    });
+    if let Some(projection) = opt.projection {
+        scene.set_projection(projection);
+    }
+    if opt.no_bvh {
+        scene.disable_bvh();
+    }
+    if opt.spectral {
+        scene.set_spectral(true);
+    }
+    let resolution = opt.resolution.or(scene_file.resolution).unwrap_or(Resolution::new(600, 600));
+    let samples = opt.samples.or(scene_file.samples).unwrap_or(128);
+    let resolution = Resolution::new(opt.width.unwrap_or(resolution.width), opt.height.unwrap_or(resolution.height));
+    let image = if opt.threshold.is_some() || opt.time_limit.is_some() {
+        let settings = Progressive{
+            threshold: opt.threshold.unwrap_or(0.0),
+            max_samples: if opt.time_limit.is_some() { opt.samples } else { Some(samples) },
+            time_limit: opt.time_limit,
+        };
+        scene.render_progressive(resolution, &settings)
+    } else {
+        scene.render(resolution, samples)
+    };
+    // a physical camera brings its own exposure, --exposure corrects it
+    let exposure = opt.exposure + scene.camera().exposure();
+    let tonemap = Tonemap{tonemapper: opt.tonemap, exposure, auto_exposure: opt.auto_exposure};
+    image.save(out_path, &tonemap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        let frames = |s: &str| parse_frames(s).map(|f| (f.first, f.last));
+        assert_eq!(frames("120"), Ok((1, 120)));
+        assert_eq!(frames("1..120"), Ok((1, 120)));
+        assert_eq!(frames("5..5"), Ok((5, 5)));
+        for invalid in ["5..1", "1..", "..3", "-1..3", "1...3", "a", ""] {
+            assert!(frames(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn frame_paths() {
+        assert_eq!(frame_path(Path::new("out/img.png"), 1, ""), PathBuf::from("out/img_0001.png"));
+        assert_eq!(frame_path(Path::new("img.png"), 12345, ""), PathBuf::from("img_12345.png"));
+        assert_eq!(frame_path(Path::new("img"), 7, ""), PathBuf::from("img_0007"));
+        assert_eq!(frame_path(Path::new("img.tar.exr"), 2, ".partial"), PathBuf::from("img.tar_0002.partial.exr"));
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        for invalid in ["1e400", "99999999999999999999999h", "2d", "-1s", "s", ""] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn rate() {
+        assert_eq!(parse_rate("24"), Ok(24.0));
+        assert_eq!(parse_rate("0.5"), Ok(0.5));
+        for invalid in ["0", "-24", "NaN", "inf", "fast", ""] {
+            assert!(parse_rate(invalid).is_err(), "{}", invalid);
+        }
+    }
 }
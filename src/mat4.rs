@@ -0,0 +1,179 @@
+use crate::vec3::{Vec3, Vec3n};
+use crate::bvh::Aabb;
+
+use std::ops;
+
+// affine transform, m[row][column] with the translation in the last column. The last row
+// is always 0 0 0 1 and not used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::identity()
+    }
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4::scaling(&Vec3::from(1.0))
+    }
+
+    pub fn translation(v: &Vec3) -> Mat4 {
+        Mat4{m: [
+            [1.0, 0.0, 0.0, v.x],
+            [0.0, 1.0, 0.0, v.y],
+            [0.0, 0.0, 1.0, v.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]}
+    }
+
+    pub fn scaling(v: &Vec3) -> Mat4 {
+        Mat4{m: [
+            [v.x, 0.0, 0.0, 0.0],
+            [0.0, v.y, 0.0, 0.0],
+            [0.0, 0.0, v.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]}
+    }
+
+    // counterclockwise around `axis` when looking against it
+    pub fn rotation(axis: &Vec3n, degrees: f32) -> Mat4 {
+        let a = Vec3::from(axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Mat4{m: [
+            [t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+            [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+            [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]}
+    }
+
+    // of the linear part, negative if the transform mirrors
+    pub fn determinant(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // the factor of a transform that scales all directions alike, None if it stretches
+    // some more than others
+    pub fn uniform_scale(&self) -> Option<f32> {
+        let [x, y, z] = [0, 1, 2].map(|c| Vec3{x: self.m[0][c], y: self.m[1][c], z: self.m[2][c]});
+        let scale = x.len();
+        let tolerance = 1e-4 * scale * scale;
+        let orthogonal = (x * y).abs() < tolerance && (y * z).abs() < tolerance && (x * z).abs() < tolerance;
+        let equal = (y.len_squared() - scale * scale).abs() < tolerance && (z.len_squared() - scale * scale).abs() < tolerance;
+        if orthogonal && equal { Some(scale) } else { None }
+    }
+
+    // how much the area of a flat surface facing `normal` grows
+    pub fn area_scale(&self, normal: &Vec3n) -> f32 {
+        match self.inverse() {
+            Some(inverse) => self.determinant().abs() * inverse.transpose().transform_vector(&Vec3::from(normal)).len(),
+            None => 0.0
+        }
+    }
+
+    // None if the transform collapses space onto a plane, line or point
+    pub fn inverse(&self) -> Option<Mat4> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 { return None }
+        let m = &self.m;
+        // the inverse of the linear part is its adjugate divided by the determinant
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / det;
+        let linear = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let mut inverse = Mat4::identity();
+        for (row, linear) in inverse.m.iter_mut().zip(&linear) {
+            row[..3].copy_from_slice(linear);
+            row[3] = -(0..3).map(|c| linear[c] * m[c][3]).sum::<f32>();
+        }
+        Some(inverse)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut transposed = *self;
+        for r in 0..3 {
+            for c in 0..3 {
+                transposed.m[r][c] = self.m[c][r];
+            }
+            transposed.m[r][3] = 0.0;
+        }
+        transposed
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        self.transform_vector(p) + Vec3{x: self.m[0][3], y: self.m[1][3], z: self.m[2][3]}
+    }
+
+    // directions ignore the translation
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3{
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    // box around the transformed corners
+    pub fn transform_bounds(&self, bounds: &Aabb) -> Aabb {
+        (0..8).fold(Aabb::empty(), |b, corner| {
+            let pick = |bit: usize, min: f32, max: f32| if corner & bit == 0 { min } else { max };
+            b.grow(&self.transform_point(&Vec3{
+                x: pick(1, bounds.min.x, bounds.max.x),
+                y: pick(2, bounds.min.y, bounds.max.y),
+                z: pick(4, bounds.min.z, bounds.max.z),
+            }))
+        })
+    }
+}
+
+// `a * b` applies `b` first
+impl_op_ex!(* |a: &Mat4, b: &Mat4| -> Mat4 {
+    let mut m = [[0.0; 4]; 4];
+    for (r, row) in m.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a.m[r][k] * b.m[k][c]).sum();
+        }
+    }
+    Mat4{m}
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        assert!((a - b).len() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn rotation_and_inverse() {
+        let rotation = Mat4::rotation(&Vec3n::new(0.0, 0.0, 1.0), 90.0);
+        assert_close(&rotation.transform_vector(&Vec3{x: 1.0, y: 0.0, z: 0.0}), &Vec3{x: 0.0, y: 1.0, z: 0.0});
+
+        let transform = Mat4::translation(&Vec3{x: 1.0, y: -2.0, z: 3.0})
+            * Mat4::rotation(&Vec3n::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scaling(&Vec3{x: 2.0, y: 0.5, z: 1.5});
+        let p = Vec3{x: 0.3, y: 0.7, z: -1.1};
+        let inverse = transform.inverse().unwrap();
+        assert_close(&inverse.transform_point(&transform.transform_point(&p)), &p);
+        assert_close(&(transform * inverse).transform_point(&p), &p);
+        assert!((transform.determinant() - 1.5).abs() < 1e-5);
+        assert!(Mat4::scaling(&Vec3{x: 1.0, y: 0.0, z: 1.0}).inverse().is_none());
+        assert!(transform.uniform_scale().is_none());
+        // the unit square in the xy plane stretched by 2 and 0.5 and turned
+        assert!((transform.area_scale(&Vec3n::new(0.0, 0.0, 1.0)) - 1.0).abs() < 1e-5);
+        let similar = Mat4::translation(&Vec3::from(1.0)) * Mat4::rotation(&Vec3n::new(1.0, 1.0, 0.0), 30.0) * Mat4::scaling(&Vec3::from(-2.0));
+        assert!((similar.uniform_scale().unwrap() - 2.0).abs() < 1e-5);
+    }
+}
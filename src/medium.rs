@@ -0,0 +1,288 @@
+use crate::color::Color;
+use crate::ray::{Ray, Intersection};
+use crate::texture::Texture;
+use crate::vec3::{Vec3, Vec3n};
+use crate::random::sample_rng;
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+// Henyey-Greenstein phase function, `g` > 0 scatters forward, `g` < 0 backward and
+// 0 uniformly in all directions
+#[derive(Debug, Clone, Copy)]
+pub struct HenyeyGreenstein {
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f32) -> HenyeyGreenstein {
+        HenyeyGreenstein{g: g.clamp(-0.99, 0.99)}
+    }
+
+    // density per solid angle, `cos_theta` is between the direction the light travels
+    // in and the scattered direction
+    pub fn eval(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+    }
+
+    // scattered direction for light travelling along `direction`, its pdf is `eval`
+    pub fn sample(&self, direction: &Vec3n, u1: f32, u2: f32) -> Vec3n {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        }.clamp(-1.0, 1.0);
+        let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * PI * u2;
+        let (tangent, bitangent) = direction.tangent_frame(&Vec3::default());
+        Vec3n::from(sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * direction)
+    }
+}
+
+// what happens to a ray on its way through a medium to `t_max`, the weights are the
+// change of the throughput divided by the probability of the event
+pub enum MediumEvent {
+    // scattered at distance `t`, the phase function picks the new direction
+    Scattered{t: f32, weight: Color},
+    Passed{weight: Color},
+    Absorbed,
+}
+
+// a volume that absorbs and scatters light, bounded by the surface of an object or
+// filling the whole scene. Coefficients are per unit of length
+pub trait Medium {
+    // the throughput of the path so far decides which color channels the distances are
+    // sampled for, which keeps the weights of colored media bounded
+    fn sample(&self, ray: &Ray, t_max: f32, throughput: &Color) -> MediumEvent;
+    // fraction of the light that passes from the origin of the ray to `t_max`
+    fn transmittance(&self, ray: &Ray, t_max: f32) -> Color;
+    fn phase(&self) -> &HenyeyGreenstein;
+}
+
+fn average(c: &Color) -> f32 {
+    (c.r + c.g + c.b) / 3.0
+}
+
+fn channel(c: &Color, i: usize) -> f32 {
+    match i {
+        0 => c.r,
+        1 => c.g,
+        _ => c.b,
+    }
+}
+
+// probabilities of picking each channel, proportional to the throughput
+fn channel_probabilities(throughput: &Color) -> Color {
+    let sum = throughput.r + throughput.g + throughput.b;
+    if sum > 0.0 { *throughput / sum } else { Color::new(1.0 / 3.0) }
+}
+
+fn dot(a: &Color, b: &Color) -> f32 {
+    a.r * b.r + a.g * b.g + a.b * b.b
+}
+
+// the same density everywhere
+pub struct Homogeneous {
+    pub absorption: Color,
+    pub scattering: Color,
+    pub phase: HenyeyGreenstein,
+}
+
+impl Homogeneous {
+    pub fn new(absorption: Color, scattering: Color, g: f32) -> Homogeneous {
+        Homogeneous{absorption, scattering, phase: HenyeyGreenstein::new(g)}
+    }
+
+    // a clear medium like colored glass, absorbing light by the beer-lambert law so that
+    // `color` is left after travelling `distance`. Channels above 1 would have to add light
+    // and are clamped
+    pub fn absorbing(color: &Color, distance: f32) -> Homogeneous {
+        let absorption = color.map(|c| -c.clamp(1e-6, 1.0).ln() / distance);
+        Homogeneous::new(absorption, Color::default(), 0.0)
+    }
+}
+
+impl Medium for Homogeneous {
+    // distances are sampled for a random channel and weighted by the pdf averaged over all
+    // channels the same way
+    fn sample(&self, ray: &Ray, t_max: f32, throughput: &Color) -> MediumEvent {
+        let transmittance = self.transmittance(ray, t_max);
+        let scattering = ray.to_spectrum(self.scattering);
+        if scattering.r <= 0.0 && scattering.g <= 0.0 && scattering.b <= 0.0 {
+            return MediumEvent::Passed{weight: transmittance}
+        }
+
+        let mut rng = sample_rng();
+        let extinction = ray.to_spectrum(self.absorption) + scattering;
+        let probabilities = channel_probabilities(throughput);
+        let u = rng.gen_range(0.0..1.0);
+        let picked = if u < probabilities.r { 0 } else if u < probabilities.r + probabilities.g { 1 } else { 2 };
+        let sigma = channel(&extinction, picked);
+        let t = if sigma > 0.0 { -f32::ln(1.0 - rng.gen_range(0.0..1.0)) / sigma } else { f32::INFINITY };
+        if t < t_max {
+            let transmittance = (extinction * -t).map(f32::exp);
+            let pdf = dot(&probabilities, &(extinction * transmittance));
+            MediumEvent::Scattered{t, weight: scattering * transmittance / pdf}
+        } else {
+            let pdf = dot(&probabilities, &transmittance);
+            let weight = if pdf > 0.0 { transmittance / pdf } else { Color::default() };
+            MediumEvent::Passed{weight}
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f32) -> Color {
+        let extinction = ray.to_spectrum(self.absorption + self.scattering);
+        (extinction * -t_max).map(|x| if x.is_nan() { 1.0 } else { x.exp() })
+    }
+
+    fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+}
+
+// coefficients scaled by a density texture, like smoke or clouds. The texture is looked
+// up at positions in space, its luminance clamped to [0, 1] is the density. Only solid
+// textures vary there, uv mapped ones see the same coordinates everywhere
+pub struct Heterogeneous {
+    pub absorption: Color,
+    pub scattering: Color,
+    pub density: Arc<dyn Texture + Send + Sync>,
+    pub phase: HenyeyGreenstein,
+}
+
+impl Heterogeneous {
+    pub fn new(absorption: Color, scattering: Color, density: Arc<dyn Texture + Send + Sync>, g: f32) -> Heterogeneous {
+        Heterogeneous{absorption, scattering, density, phase: HenyeyGreenstein::new(g)}
+    }
+
+    fn density(&self, pos: Vec3) -> f32 {
+        let up = Vec3n::new(0.0, 0.0, 1.0);
+        let intersection = Intersection::new(pos, up, &Vec3{x: 1.0, y: 0.0, z: 0.0}, (0.0, 0.0), 0.0);
+        self.density.value(&intersection).luminance().clamp(0.0, 1.0)
+    }
+
+    // the extinction at full density bounds it everywhere, tentative collisions are
+    // sampled with it and then rejected as null collisions where the medium is thinner
+    fn majorant(extinction: &Color) -> f32 {
+        extinction.r.max(extinction.g).max(extinction.b)
+    }
+}
+
+impl Medium for Heterogeneous {
+    // delta tracking, with spectral tracking weights for colored media
+    fn sample(&self, ray: &Ray, t_max: f32, throughput: &Color) -> MediumEvent {
+        let mut rng = sample_rng();
+        let absorption = ray.to_spectrum(self.absorption);
+        let scattering = ray.to_spectrum(self.scattering);
+        let majorant = Heterogeneous::majorant(&(absorption + scattering));
+        if majorant <= 0.0 { return MediumEvent::Passed{weight: Color::new(1.0)} }
+
+        let mut weight = Color::new(1.0);
+        let mut t = 0.0;
+        loop {
+            t -= f32::ln(1.0 - rng.gen_range(0.0..1.0)) / majorant;
+            if t >= t_max { return MediumEvent::Passed{weight} }
+
+            let density = self.density(ray.at(t));
+            let scattering = scattering * density;
+            let null = Color::new(majorant) - absorption * density - scattering;
+            // the probabilities of the events follow the throughput including this medium
+            let current = *throughput * weight;
+            let total = majorant * average(&current);
+            if total <= 0.0 { return MediumEvent::Absorbed }
+            let (p_scatter, p_null) = (average(&(scattering * current)) / total, average(&(null * current)) / total);
+
+            // the remaining probability is absorption, which ends the path
+            let u = rng.gen_range(0.0..1.0);
+            if u < p_scatter {
+                return MediumEvent::Scattered{t, weight: weight * scattering / (majorant * p_scatter)}
+            } else if u < p_scatter + p_null {
+                weight = weight * null / (majorant * p_null);
+            } else {
+                return MediumEvent::Absorbed
+            }
+        }
+    }
+
+    // ratio tracking
+    fn transmittance(&self, ray: &Ray, t_max: f32) -> Color {
+        let mut rng = sample_rng();
+        let extinction = ray.to_spectrum(self.absorption + self.scattering);
+        let majorant = Heterogeneous::majorant(&extinction);
+        if majorant <= 0.0 { return Color::new(1.0) }
+
+        let mut transmittance = Color::new(1.0);
+        let mut t = 0.0;
+        loop {
+            t -= f32::ln(1.0 - rng.gen_range(0.0..1.0)) / majorant;
+            if t >= t_max { return transmittance }
+            transmittance = transmittance * (Color::new(1.0) - extinction * (self.density(ray.at(t)) / majorant));
+            if transmittance.r.max(transmittance.g).max(transmittance.b) <= 0.0 { return transmittance }
+        }
+    }
+
+    fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the phase function integrates to one over the sphere and its samples follow it
+    #[test]
+    fn henyey_greenstein() {
+        for g in [-0.5, 0.0, 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(g);
+            let n = 10000;
+            let integral = (0..n).map(|i| {
+                let cos_theta = -1.0 + 2.0 * (i as f32 + 0.5) / n as f32;
+                phase.eval(cos_theta) * 2.0 * PI * 2.0 / n as f32
+            }).sum::<f32>();
+            assert!((integral - 1.0).abs() < 1e-3, "g = {}: {}", g, integral);
+
+            // the mean cosine of the samples is g
+            let direction = Vec3n::new(0.0, 1.0, 0.0);
+            let mut rng = StdRng::seed_from_u64(1);
+            let mean = (0..n).map(|_| phase.sample(&direction, rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)) * direction)
+                .sum::<f32>() / n as f32;
+            assert!((mean - g).abs() < 0.02, "g = {}: {}", g, mean);
+        }
+    }
+
+    // `color` is what is left after `distance`, and light never grows on its way
+    #[test]
+    fn absorbing_color() {
+        let ray = Ray::new(Vec3::default(), Vec3n::new(1.0, 0.0, 0.0));
+        let medium = Homogeneous::absorbing(&Color{r: 0.5, g: 1.5, b: 0.0}, 2.0);
+        let t = medium.transmittance(&ray, 2.0);
+        assert!((t.r - 0.5).abs() < 1e-5 && t.g == 1.0 && t.b < 1e-5, "{:?}", t);
+        assert!(medium.transmittance(&ray, 100.0).g <= 1.0);
+    }
+
+    // delta and ratio tracking through a constant density agree with beer-lambert
+    #[test]
+    fn heterogeneous_transmittance() {
+        let ray = Ray::new(Vec3::default(), Vec3n::new(1.0, 0.0, 0.0));
+        let medium = Heterogeneous::new(Color{r: 0.2, g: 0.5, b: 1.0}, Color::new(0.5), Arc::new(Color::new(0.5)), 0.0);
+        let expected = Homogeneous::new(medium.absorption * 0.5, medium.scattering * 0.5, 0.0).transmittance(&ray, 2.0);
+
+        crate::random::seed(1);
+        let n = 2000;
+        let ratio = (0..n).map(|_| medium.transmittance(&ray, 2.0)).fold(Color::default(), |a, b| a + b) / n as f32;
+        let passed = (0..n).map(|_| match medium.sample(&ray, 2.0, &Color::new(1.0)) {
+            MediumEvent::Passed{weight} => weight,
+            _ => Color::default(),
+        }).fold(Color::default(), |a, b| a + b) / n as f32;
+        for (a, b) in [(ratio.r, expected.r), (ratio.b, expected.b), (passed.r, expected.r), (passed.b, expected.b)] {
+            assert!((a - b).abs() < 0.05, "{} != {}", a, b);
+        }
+    }
+}
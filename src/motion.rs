@@ -0,0 +1,192 @@
+use crate::vec3::{Vec3, Vec3n};
+use crate::mat4::Mat4;
+use crate::bvh::Aabb;
+use crate::animation::{self, Interpolation};
+
+// the pose of a moving object or camera at one moment
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vec3,
+    // degrees around x, y and z, applied in this order. The angles are interpolated, so
+    // an object can turn more than half a circle between two keyframes
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    // towards the next keyframe
+    pub interpolation: Interpolation,
+}
+
+impl Keyframe {
+    // no motion at `time`
+    pub fn new(time: f32) -> Keyframe {
+        Keyframe{time, translation: Vec3::default(), rotation: Vec3::default(), scale: Vec3::from(1.0), interpolation: Interpolation::Linear}
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        let r = &self.rotation;
+        Mat4::translation(&self.translation)
+            * Mat4::rotation(&Vec3n::new(0.0, 0.0, 1.0), r.z)
+            * Mat4::rotation(&Vec3n::new(0.0, 1.0, 0.0), r.y)
+            * Mat4::rotation(&Vec3n::new(1.0, 0.0, 0.0), r.x)
+            * Mat4::scaling(&self.scale)
+    }
+}
+
+// a transform that changes over time, interpolated between the keyframes and constant
+// before the first and after the last one
+#[derive(Debug, Clone)]
+pub struct Motion {
+    keyframes: Vec<Keyframe>,
+    times: Vec<f32>,
+    interpolations: Vec<Interpolation>,
+}
+
+impl Motion {
+    // needs at least one keyframe, their order does not matter
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Motion {
+        assert!(!keyframes.is_empty(), "motion without keyframes");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let times = keyframes.iter().map(|k| k.time).collect();
+        let interpolations = keyframes.iter().map(|k| k.interpolation).collect();
+        Motion{keyframes, times, interpolations}
+    }
+
+    pub fn keyframe(&self, time: f32) -> Keyframe {
+        let mut keyframe = Keyframe{translation: Vec3::default(), rotation: Vec3::default(), scale: Vec3::default(), ..Keyframe::new(time)};
+        for (i, w) in animation::weights(&self.times, &self.interpolations, time) {
+            let k = &self.keyframes[i];
+            keyframe.translation = keyframe.translation + w * k.translation;
+            keyframe.rotation = keyframe.rotation + w * k.rotation;
+            keyframe.scale = keyframe.scale + w * k.scale;
+        }
+        keyframe
+    }
+
+    pub fn at(&self, time: f32) -> Mat4 {
+        self.keyframe(time).transform()
+    }
+
+    // box around `bounds` at every moment of the motion. Steps of at most about 2° are
+    // taken and the result grows by how far a turning corner can leave the straight line
+    // between two steps. Bezier curves can also bend away from that line while moving,
+    // so their steps grow by the largest move between two samples of the same segment
+    pub fn bounds(&self, bounds: &Aabb) -> Aabb {
+        let corner = |i: usize| Vec3{
+            x: if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+            y: if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+            z: if i & 4 == 0 { bounds.min.z } else { bounds.max.z },
+        };
+        let radius = (0..8).map(|i| corner(i).len()).fold(0.0, f32::max);
+        let largest = |s: &Vec3| s.x.abs().max(s.y.abs()).max(s.z.abs());
+
+        let turn = |a: &Keyframe, b: &Keyframe| {
+            let turn = b.rotation - a.rotation;
+            turn.x.abs() + turn.y.abs() + turn.z.abs()
+        };
+
+        let last = self.keyframes[self.keyframes.len() - 1];
+        let mut result = last.transform().transform_bounds(bounds);
+        for pair in self.keyframes.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            // bezier curves may swing past the keyframes and are followed more closely
+            let steps = match a.interpolation {
+                Interpolation::Linear => (turn(a, b) / 2.0).ceil().max(1.0),
+                Interpolation::Bezier => (turn(a, b)).ceil().max(32.0),
+            } as usize;
+            let samples: Vec<Keyframe> = (0..=steps)
+                .map(|i| self.keyframe(a.time + (b.time - a.time) * i as f32 / steps as f32))
+                .collect();
+            // a linear move stays inside the box of its ends
+            let mut shift = Vec3::default();
+            if a.interpolation == Interpolation::Bezier {
+                for step in samples.windows(2) {
+                    let d = step[1].translation - step[0].translation;
+                    shift = Vec3{x: shift.x.max(d.x.abs()), y: shift.y.max(d.y.abs()), z: shift.z.max(d.z.abs())};
+                }
+            }
+            for step in samples.windows(2) {
+                let (previous, next) = (&step[0], &step[1]);
+                let scale = largest(&previous.scale).max(largest(&next.scale));
+                let pad = shift + Vec3::from(radius * scale * (1.0 - (turn(previous, next) / 2.0).to_radians().cos()));
+                let step = previous.transform().transform_bounds(bounds);
+                result = result.union(&Aabb::new(step.min - pad, step.max + pad));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a point spinning a full turn around the origin stays inside the bounds at all times
+    #[test]
+    fn spinning_bounds() {
+        let mut end = Keyframe::new(1.0);
+        end.rotation = Vec3{x: 0.0, y: 0.0, z: 360.0};
+        end.translation = Vec3{x: 0.0, y: 0.0, z: 2.0};
+        let motion = Motion::new(vec![end, Keyframe::new(0.0)]);
+
+        let p = Vec3{x: 1.0, y: 0.5, z: 0.0};
+        let bounds = motion.bounds(&Aabb::new(p, p));
+        for i in 0..=1000 {
+            let q = motion.at(i as f32 / 1000.0).transform_point(&p);
+            assert!(q.x >= bounds.min.x && q.y >= bounds.min.y && q.z >= bounds.min.z, "{:?} outside {:?}", q, bounds);
+            assert!(q.x <= bounds.max.x && q.y <= bounds.max.y && q.z <= bounds.max.z, "{:?} outside {:?}", q, bounds);
+        }
+        // a circle of radius |p| that moves up by 2
+        let r = p.len();
+        assert!((bounds.max.x - r).abs() < 1e-3 && (bounds.min.y + r).abs() < 1e-3 && (bounds.max.z - 2.0).abs() < 1e-4);
+
+        // half way through the turn
+        let half = motion.at(0.5).transform_point(&p);
+        assert!((half - Vec3{x: -1.0, y: -0.5, z: 1.0}).len() < 1e-5, "{:?}", half);
+        assert_eq!(motion.keyframe(-1.0).rotation.z, 0.0);
+    }
+
+    // bezier curves swing past their keyframes, the bounds still hold everything
+    #[test]
+    fn bezier_bounds() {
+        let keyframes: Vec<Keyframe> = [(0.0, 0.0, 0.0), (1.0, 3.0, 90.0), (1.2, 3.2, -45.0), (3.0, -1.0, 10.0)].iter().map(|&(time, x, angle)| {
+            Keyframe{
+                translation: Vec3{x, y: 0.0, z: 0.0},
+                rotation: Vec3{x: angle, y: 0.0, z: angle},
+                interpolation: Interpolation::Bezier,
+                ..Keyframe::new(time)
+            }
+        }).collect();
+        let motion = Motion::new(keyframes);
+        let corner = Vec3{x: 0.5, y: 0.5, z: 0.5};
+        let bounds = motion.bounds(&Aabb::new(Vec3::default() - corner, corner));
+        for i in 0..=3000 {
+            for c in 0..8 {
+                let p = Vec3{
+                    x: if c & 1 == 0 { -0.5 } else { 0.5 },
+                    y: if c & 2 == 0 { -0.5 } else { 0.5 },
+                    z: if c & 4 == 0 { -0.5 } else { 0.5 },
+                };
+                let q = motion.at(i as f32 / 1000.0).transform_point(&p);
+                assert!(q.x >= bounds.min.x && q.y >= bounds.min.y && q.z >= bounds.min.z, "{:?} outside {:?}", q, bounds);
+                assert!(q.x <= bounds.max.x && q.y <= bounds.max.y && q.z <= bounds.max.z, "{:?} outside {:?}", q, bounds);
+            }
+        }
+    }
+
+    // a bezier move that swings far past its keyframes between the samples
+    #[test]
+    fn bezier_translation_bounds() {
+        let keyframes: Vec<Keyframe> = [(0.0, 0.0), (1.0, 0.0), (1.001, 100.0), (2.0, 100.0)].iter().map(|&(time, x)| {
+            Keyframe{translation: Vec3{x, y: 0.0, z: 0.0}, interpolation: Interpolation::Bezier, ..Keyframe::new(time)}
+        }).collect();
+        let motion = Motion::new(keyframes);
+        let p = Vec3{x: 0.0, y: 0.0, z: 0.0};
+        let bounds = motion.bounds(&Aabb::new(p, p));
+        for i in 0..=20000 {
+            let q = motion.at(i as f32 / 10000.0).transform_point(&p);
+            assert!(q.x >= bounds.min.x && q.x <= bounds.max.x, "{:?} at {} outside {:?}", q, i as f32 / 10000.0, bounds);
+        }
+        // the curve dips to about -100 * 4 / 27 before it rises
+        assert!(bounds.min.x < -14.0 && bounds.min.x > -20.0, "{:?}", bounds);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::color::Color;
+use crate::error::Error;
+use crate::image::{Image, Wrap};
+use crate::texture::{Texture, ImageTexture};
+use crate::object::Shade;
+use crate::object::light::Emissive;
+use crate::object::material::{Diffuse, Metalic, Plastic, Refractive};
+use crate::object::mesh::{Geometry, Triangle, Vertex};
+use crate::vec3::{Vec3, Vec3n};
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// a part of a Wavefront OBJ file that uses a single material, geometry and material can
+// be shared by several meshes
+#[derive(Clone)]
+pub struct ObjModel {
+    pub name: String,
+    pub geometry: Arc<Geometry>,
+    pub material: Option<Arc<dyn Shade + Send + Sync>>,
+}
+
+pub fn load(path: &Path, scale: f32, offset: Vec3) -> Result<Vec<ObjModel>, Error> {
+    let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
+        .map_err(|source| Error::Obj{path: path.to_path_buf(), source})?;
+    // a missing or broken MTL file leaves the models without material
+    let materials = materials.unwrap_or_default();
+
+    // diffuse textures, relative to the OBJ file and shared between materials
+    let mut images: HashMap<String, Arc<Image>> = HashMap::new();
+    for file in materials.iter().filter_map(|m| m.diffuse_texture.as_ref()) {
+        if !images.contains_key(file) {
+            let image_path = path.parent().unwrap_or(Path::new("")).join(file);
+            images.insert(file.clone(), Arc::new(Image::load(&image_path)?));
+        }
+    }
+
+    Ok(models.into_iter().map(|model| {
+        let mesh = &model.mesh;
+        let vertex = |i: u32| {
+            let i = i as usize;
+            let pos = Vec3{x: mesh.positions[3*i], y: mesh.positions[3*i+1], z: mesh.positions[3*i+2]};
+            Vertex{
+                pos: pos * scale + offset,
+                normal: (!mesh.normals.is_empty())
+                    .then(|| Vec3{x: mesh.normals[3*i], y: mesh.normals[3*i+1], z: mesh.normals[3*i+2]})
+                    .filter(|n| n.len_squared() > 0.0)
+                    .map(Vec3n::from),
+                uv: if mesh.texcoords.is_empty() { (0., 0.) } else { (mesh.texcoords[2*i], mesh.texcoords[2*i+1]) },
+            }
+        };
+        let triangles = mesh.indices.chunks_exact(3).map(|idx| {
+            Triangle::new([vertex(idx[0]), vertex(idx[1]), vertex(idx[2])])
+        }).collect();
+        let material = mesh.material_id.and_then(|id| materials.get(id)).map(|mtl| material_from_mtl(mtl, &images));
+        ObjModel{name: model.name, geometry: Arc::new(Geometry::new(triangles)), material}
+    }).collect())
+}
+
+fn color(c: Option<[f32; 3]>) -> Option<Color> {
+    c.map(|[r, g, b]| Color{r, g, b})
+}
+
+fn is_black(c: &Color) -> bool {
+    c.r <= 0.0 && c.g <= 0.0 && c.b <= 0.0
+}
+
+// the material an MTL entry becomes, see `MtlKind::of`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MtlKind {
+    Emissive,
+    Refractive,
+    Metal,
+    Plastic,
+    Diffuse,
+}
+
+impl MtlKind {
+    // emission wins over the illumination model, dissolved materials are glass
+    fn of(mtl: &tobj::Material) -> MtlKind {
+        let specular = color(mtl.specular).unwrap_or_default();
+        match (mtl.illumination_model, mtl.dissolve) {
+            _ if emission(mtl).is_some() => MtlKind::Emissive,
+            (Some(4) | Some(6) | Some(7), _) => MtlKind::Refractive,
+            (_, Some(d)) if d < 1.0 => MtlKind::Refractive,
+            (Some(3) | Some(5), _) => MtlKind::Metal,
+            (Some(2), _) if !is_black(&specular) => MtlKind::Plastic,
+            _ => MtlKind::Diffuse,
+        }
+    }
+}
+
+// `Ke` if it is not black
+fn emission(mtl: &tobj::Material) -> Option<Color> {
+    mtl.unknown_param.get("Ke").and_then(|ke| {
+        let values: Vec<f32> = ke.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+        match values[..] {
+            [r, g, b] => Some(Color{r, g, b}),
+            _ => None
+        }
+    }).filter(|e| !is_black(e))
+}
+
+// maps the MTL illumination models onto the materials of the path tracer
+fn material_from_mtl(mtl: &tobj::Material, images: &HashMap<String, Arc<Image>>) -> Arc<dyn Shade + Send + Sync> {
+    let diffuse = color(mtl.diffuse).unwrap_or(Color::new(0.8));
+    let specular = color(mtl.specular).unwrap_or_default();
+    let ior = Color::new(mtl.optical_density.unwrap_or(1.5));
+    let diffuse_texture = || match mtl.diffuse_texture.as_ref().and_then(|file| images.get(file)) {
+        Some(image) => Arc::new(ImageTexture::new(image.clone(), Wrap::Repeat, 1.0)) as Arc<dyn Texture + Send + Sync>,
+        None => Arc::new(diffuse),
+    };
+
+    match MtlKind::of(mtl) {
+        MtlKind::Emissive => Arc::new(Emissive::new(emission(mtl).unwrap_or_default(), false)),
+        MtlKind::Refractive => Arc::new(Refractive::new(&ior)),
+        MtlKind::Metal => Arc::new(Metalic::from_f0(if is_black(&specular) { &diffuse } else { &specular })),
+        MtlKind::Plastic => {
+            // phong exponent to the beckmann like roughness, alpha = sqrt(2 / (Ns + 2))
+            let alpha = f32::sqrt(2.0 / (mtl.shininess.unwrap_or(0.0).max(0.0) + 2.0));
+            Arc::new(Plastic::new(diffuse_texture(), 1.5, alpha.sqrt()))
+        },
+        MtlKind::Diffuse => Arc::new(Diffuse::new(diffuse_texture())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Intersect;
+    use crate::ray::Ray;
+
+    fn kinds(mtl: &str) -> Vec<MtlKind> {
+        let (materials, _) = tobj::load_mtl_buf(&mut mtl.as_bytes()).unwrap();
+        materials.iter().map(MtlKind::of).collect()
+    }
+
+    #[test]
+    fn mtl_mapping() {
+        let source = "\
+newmtl glass
+illum 7
+newmtl frosted
+illum 4
+newmtl thin
+illum 6
+newmtl dissolved
+illum 2
+Ks 0.5 0.5 0.5
+d 0.5
+newmtl mirror
+illum 3
+newmtl chrome
+illum 5
+Ks 0.9 0.9 0.9
+newmtl lamp
+illum 7
+Ke 4 4 4
+newmtl unlit
+illum 2
+Ke 0 0 0
+newmtl shiny
+illum 2
+Ks 0.5 0.5 0.5
+Ns 100
+newmtl matte
+illum 2
+Ks 0 0 0
+newmtl flat
+illum 1
+Ks 0.5 0.5 0.5
+newmtl plain
+Kd 0.5 0.5 0.5
+";
+        use MtlKind::*;
+        assert_eq!(kinds(source), [Refractive, Refractive, Refractive, Refractive, Metal, Metal, Emissive, Diffuse, Plastic, Diffuse, Diffuse, Diffuse]);
+    }
+
+    // the quads of the cube are split into two triangles each and keep their material
+    #[test]
+    fn cube() {
+        let models = load(Path::new("scenes/models/cube.obj"), 2.0, Vec3{x: 1.0, y: 0.0, z: 0.0}).unwrap();
+        assert_eq!(models.len(), 1);
+        let cube = &models[0];
+        assert_eq!(cube.name, "cube");
+        assert!((cube.geometry.area() - 24.0).abs() < 1e-4, "{}", cube.geometry.area());
+        assert!(cube.material.is_some());
+        let (materials, _) = tobj::load_mtl(Path::new("scenes/models/cube.mtl")).unwrap();
+        assert_eq!(MtlKind::of(&materials[0]), MtlKind::Diffuse);
+
+        // scaled by 2 and moved by 1 along x, the face at x = 1 is hit from the front
+        let mesh = crate::object::mesh::Mesh::shared(cube.geometry.clone(), Box::new(Diffuse::new(Arc::new(Color::new(0.5)))));
+        let hit = mesh.intersect(&Ray::new(Vec3{x: -5.0, y: 1.0, z: 0.5}, Vec3n::new(1.0, 0.0, 0.0))).unwrap();
+        assert!((hit.t - 6.0).abs() < 1e-4 && (hit.normal * Vec3n::new(-1.0, 0.0, 0.0) - 1.0).abs() < 1e-4);
+        let bounds = mesh.bounds().unwrap();
+        assert!((bounds.min - Vec3{x: 1.0, y: 0.0, z: 0.0}).len() < 1e-5 && (bounds.max - Vec3{x: 3.0, y: 2.0, z: 2.0}).len() < 1e-5);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::vec3::{Vec3, Vec3n};
+use crate::object::{Object, Intersect, Shade, BsdfSample};
+use crate::ray::{Ray, Intersection};
+use crate::color::Color;
+use crate::bvh::Aabb;
+use crate::mat4::Mat4;
+use crate::motion::Motion;
+use crate::medium::Medium;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InstanceError {
+    NotInvertible,
+    // the area of the emitter under the transform is not known, see `transformed_area`
+    StretchedEmitter,
+}
+
+impl std::fmt::Display for InstanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InstanceError::NotInvertible => write!(f, "transform is not invertible"),
+            InstanceError::StretchedEmitter => write!(f, "emitters other than planes can only be scaled uniformly"),
+        }
+    }
+}
+
+// an object placed by a transform. Rays are transformed into the space of the object and
+// its intersections back into the world, so the object itself can stay at the origin.
+// Meshes made from a shared `Geometry` are cheap to place many times
+pub struct Instance {
+    object: Box<dyn Object + Sync>,
+    to_world: Mat4,
+    to_object: Mat4,
+    // only emitters need it
+    area: f32,
+    // keyframed motion in the space of the object, applied before `to_world`
+    motion: Option<Motion>,
+}
+
+impl Instance {
+    // emitters have to keep an area that is known exactly
+    pub fn new(object: Box<dyn Object + Sync>, to_world: Mat4) -> Result<Instance, InstanceError> {
+        let to_object = to_world.inverse().ok_or(InstanceError::NotInvertible)?;
+        let area = match object.transformed_area(&to_world) {
+            Some(area) => area,
+            None if object.is_emissive() => return Err(InstanceError::StretchedEmitter),
+            None => 0.0
+        };
+        Ok(Instance{object, to_world, to_object, area, motion: None})
+    }
+
+    // the area of moving emitters ignores the scaling of the motion
+    pub fn with_motion(self, motion: Motion) -> Instance {
+        Instance{motion: Some(motion), ..self}
+    }
+
+    // to world and to object space at `time`, None when the motion collapses the object
+    fn transforms(&self, time: f32) -> Option<(Mat4, Mat4)> {
+        match &self.motion {
+            None => Some((self.to_world, self.to_object)),
+            Some(motion) => {
+                let to_world = self.to_world * motion.at(time);
+                Some((to_world, to_world.inverse()?))
+            }
+        }
+    }
+}
+
+// normals are transformed by the inverse transpose to stay perpendicular to the surface
+fn normal_to_world(to_object: &Mat4, normal: &Vec3n) -> Vec3n {
+    Vec3n::from(to_object.transpose().transform_vector(&Vec3::from(normal)))
+}
+
+// `hit` in the space of the object, found at `pos` and `t` in the world
+fn intersection_to_world(to_world: &Mat4, to_object: &Mat4, hit: &Intersection, pos: Vec3, t: f32) -> Intersection {
+    let tangent = to_world.transform_vector(&Vec3::from(hit.tangent));
+    let mut intersection = Intersection::new(pos, normal_to_world(to_object, &hit.normal), &tangent, hit.uv, t);
+    intersection.geometric_normal = normal_to_world(to_object, &hit.geometric_normal);
+    // normal x tangent misses the bitangent of left handed uvs and mirroring transforms
+    if to_world.transform_vector(&Vec3::from(hit.bitangent)) * Vec3::from(intersection.bitangent) < 0.0 {
+        intersection.bitangent = -intersection.bitangent;
+    }
+    intersection
+}
+
+impl Intersect for Instance {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (to_world, to_object) = self.transforms(ray.time)?;
+        let direction = to_object.transform_vector(&Vec3::from(ray.direction));
+        // object space distances are longer by this factor
+        let stretch = direction.len();
+        let local = Ray{origin: to_object.transform_point(&ray.origin), direction: direction.norm(), ..ray.clone()};
+        let hit = self.object.intersect(&local)?;
+
+        let t = hit.t / stretch;
+        Some(intersection_to_world(&to_world, &to_object, &hit, ray.at(t), t))
+    }
+
+    // moving objects are bounded over their whole motion
+    fn bounds(&self) -> Option<Aabb> {
+        let bounds = self.object.bounds()?;
+        let bounds = match &self.motion {
+            Some(motion) => motion.bounds(&bounds),
+            None => bounds
+        };
+        Some(self.to_world.transform_bounds(&bounds))
+    }
+
+    fn area(&self) -> f32 {
+        self.area
+    }
+
+    fn sample_surface(&self, time: f32) -> Option<Intersection> {
+        let (to_world, to_object) = self.transforms(time)?;
+        let hit = self.object.sample_surface(time)?;
+        Some(intersection_to_world(&to_world, &to_object, &hit, to_world.transform_point(&hit.pos), 0.0))
+    }
+}
+
+impl Shade for Instance {
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        self.object.sample(intersection, ray)
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        self.object.emitted(intersection, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.object.is_emissive()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.object.is_interface()
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        self.object.eval(intersection, ray, direction)
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        self.object.pdf(intersection, ray, direction)
+    }
+}
+
+impl Object for Instance {
+    fn medium(&self) -> Option<&(dyn Medium + Send + Sync)> {
+        self.object.medium()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::material::Diffuse;
+    use crate::object::sphere::Sphere;
+
+    use std::sync::Arc;
+
+    fn sphere(center: Vec3, r: f32) -> Box<dyn Object + Sync> {
+        Box::new(Sphere::new(center, r, Box::new(Diffuse::new(Arc::new(Color::new(0.5))))))
+    }
+
+    // a unit sphere moved and scaled by an instance is hit like the sphere placed directly
+    #[test]
+    fn transformed_sphere() {
+        let center = Vec3{x: 1.0, y: 2.0, z: -1.0};
+        let direct = sphere(center, 2.0);
+        let instance = Instance::new(sphere(Vec3::default(), 1.0), Mat4::translation(&center) * Mat4::scaling(&Vec3::from(2.0))).unwrap();
+        assert!((instance.area() - direct.area()).abs() < 1e-3);
+
+        let ray = Ray::new(Vec3{x: 0.0, y: -5.0, z: 0.0}, Vec3n::new(0.2, 1.0, -0.1));
+        let (a, b) = (direct.intersect(&ray).unwrap(), instance.intersect(&ray).unwrap());
+        assert!((a.t - b.t).abs() < 1e-4, "{} != {}", a.t, b.t);
+        assert!((a.pos - b.pos).len() < 1e-4);
+        assert!((a.normal - b.normal).len() < 1e-4);
+    }
+
+    // a sphere moving along x is hit where it is at the time of the ray
+    #[test]
+    fn moving_sphere() {
+        use crate::motion::{Motion, Keyframe};
+        let mut end = Keyframe::new(1.0);
+        end.translation = Vec3{x: 4.0, y: 0.0, z: 0.0};
+        let instance = Instance::new(sphere(Vec3::default(), 1.0), Mat4::identity()).unwrap().with_motion(Motion::new(vec![Keyframe::new(0.0), end]));
+        let bounds = instance.bounds().unwrap();
+        assert!((bounds.min.x + 1.0).abs() < 1e-5 && (bounds.max.x - 5.0).abs() < 1e-5);
+
+        for time in [0.0, 0.25, 1.0] {
+            let ray = Ray{time, ..Ray::new(Vec3{x: 4.0 * time, y: -5.0, z: 0.0}, Vec3n::new(0.0, 1.0, 0.0))};
+            let hit = instance.intersect(&ray).unwrap();
+            assert!((hit.t - 4.0).abs() < 1e-4 && (hit.normal * Vec3n::new(0.0, -1.0, 0.0) - 1.0).abs() < 1e-4);
+            let pos = instance.sample_surface(time).unwrap().pos;
+            assert!((pos - Vec3{x: 4.0 * time, y: 0.0, z: 0.0}).len() < 1.0 + 1e-4);
+        }
+        let missed = Ray::new(Vec3{x: 4.0, y: -5.0, z: 0.0}, Vec3n::new(0.0, 1.0, 0.0));
+        assert!(instance.intersect(&missed).is_none());
+    }
+
+    // a normal map tilting towards increasing v tilts the shading normal towards the
+    // world direction of v, for left handed uvs and for a mirrored instance alike
+    #[test]
+    fn mirrored_normal_map() {
+        use crate::object::material::{Perturbed, Perturbation};
+        use crate::object::mesh::{Mesh, Triangle, Vertex};
+        // v increases along -y, left handed for a triangle facing up
+        let vertex = |x: f32, y: f32| Vertex{pos: Vec3{x, y, z: 0.0}, normal: None, uv: (x, -y)};
+        let mesh = || {
+            let material = Perturbed::new(Box::new(Diffuse::new(Arc::new(Color::new(1.0)))), Perturbation::NormalMap(Arc::new(Color{r: 0.5, g: 1.0, b: 1.0})));
+            Box::new(Mesh::new(vec![Triangle::new([vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)])], Box::new(material))) as Box<dyn Object + Sync>
+        };
+        for (mirror, x) in [(1.0, 0.2), (-1.0, -0.2)] {
+            let instance = Instance::new(mesh(), Mat4::scaling(&Vec3{x: mirror, y: 1.0, z: 1.0})).unwrap();
+            let ray = Ray::new(Vec3{x, y: 0.2, z: 1.0}, Vec3n::new(0.0, 0.0, -1.0));
+            let hit = instance.intersect(&ray).unwrap();
+            assert!((Vec3::from(hit.bitangent) - Vec3{x: 0.0, y: -1.0, z: 0.0}).len() < 1e-4, "{:?} mirrored {}", hit.bitangent, mirror);
+            let towards_v = instance.eval(&hit, &ray, &Vec3n::new(0.0, -1.0, 1.0)).r;
+            let against_v = instance.eval(&hit, &ray, &Vec3n::new(0.0, 1.0, 1.0)).r;
+            assert!(towards_v > 0.3 && against_v < 1e-4, "{} {} mirrored {}", towards_v, against_v, mirror);
+        }
+    }
+
+    // normals of a stretched sphere stay perpendicular to its surface
+    #[test]
+    fn ellipsoid_normal() {
+        let instance = Instance::new(sphere(Vec3::default(), 1.0), Mat4::scaling(&Vec3{x: 4.0, y: 1.0, z: 1.0})).unwrap();
+        let ray = Ray::new(Vec3{x: 2.0, y: -5.0, z: 0.0}, Vec3n::new(0.0, 1.0, 0.0));
+        let hit = instance.intersect(&ray).unwrap();
+        // on x²/16 + y² = 1 the gradient is (x/16, y)
+        let expected = Vec3n::new(hit.pos.x / 16.0, hit.pos.y, 0.0);
+        assert!((hit.pos.x - 2.0).abs() < 1e-4 && (hit.pos.y + f32::sqrt(0.75)).abs() < 1e-4);
+        assert!((hit.normal - expected).len() < 1e-4);
+    }
+}
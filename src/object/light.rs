@@ -1,64 +1,201 @@
 use crate::color::Color;
-use crate::image::{Image, Resolution, ImageError};
-use crate::object::{Shade, ScatteredRay};
+use crate::image::{Image, Resolution, Wrap};
+use crate::error::Error;
+use crate::distribution::Distribution2D;
+use crate::object::{Shade, BsdfSample};
 use crate::ray::{Intersection, Ray};
 use crate::vec3::{Vec3, Vec3n};
 
-use std::path::PathBuf;
+use std::f32::consts::PI;
+use std::path::Path;
 
+// light arriving from infinitely far away, stored as an equirectangular (lat-long) image
+// with +z at the top row and +x in the center column
 pub struct AmbientLight {
-    image: Image
+    image: Image,
+    // rotation around the z axis in radians
+    rotation: f32,
+    intensity: f32,
+    // importance sampling proportional to the luminance, None if the image is black
+    distribution: Option<Distribution2D>,
 }
 
 impl AmbientLight {
 
+    // orange at the horizon blending into a blue sky
     pub fn new() -> AmbientLight {
-        let resolution = Resolution{height: 1024, width: 1024};
+        let resolution = Resolution{height: 256, width: 1};
         let mut image = Image::new(resolution);
         let color_sky = Color::from_hex("#0396A6").unwrap();
         let color_orange = Color::from_hex("#D14F0F").unwrap();
-        image.get_data_mut().iter_mut().enumerate().for_each(|(idx, color)| {
-            let (h,w) = resolution.get_height_width(idx);
-            let f = (h as f32 / resolution.height as f32);
-            *color = (color_sky * (f)  + color_orange * (1.0 - f)) / 1.0
+        image.get_data_mut().iter_mut().enumerate().for_each(|(row, color)| {
+            let f = 1.0 - (row as f32 + 0.5) / resolution.height as f32;
+            *color = color_sky * f + color_orange * (1.0 - f)
         });
+        AmbientLight::from_image(image)
+    }
 
-        AmbientLight{image}
+    pub fn uniform(color: Color) -> AmbientLight {
+        let resolution = Resolution{height: 1, width: 1};
+        let mut image = Image::new(resolution);
+        image.get_data_mut()[0] = color;
+        AmbientLight::from_image(image)
     }
 
-    pub fn load(filename: &PathBuf) -> Result<AmbientLight, ImageError> {
-        let image = Image::load(filename)?;
-        Ok(AmbientLight{image})
+    pub fn load(filename: &Path) -> Result<AmbientLight, Error> {
+        Ok(AmbientLight::from_image(Image::load(filename)?))
+    }
+
+    pub fn from_image(image: Image) -> AmbientLight {
+        let Resolution{width, height} = image.resolution;
+        // the maximum over the neighbourhood covers everything the bilinear lookup can return,
+        // sin(theta) accounts for the smaller solid angle of the rows near the poles
+        let func: Vec<f32> = (0..width * height).map(|i| {
+            let (row, col) = (i / width, i % width);
+            let sin_theta = f32::sin(PI * (row as f32 + 0.5) / height as f32);
+            let max = (-1..=1).flat_map(|dr| (-1..=1).map(move |dc| (dr, dc))).map(|(dr, dc)| {
+                let r = (row as isize + dr).clamp(0, height as isize - 1) as usize;
+                let c = (col as isize + dc).rem_euclid(width as isize) as usize;
+                image.get_data()[r * width + c].luminance()
+            }).fold(0.0, f32::max);
+            max * sin_theta
+        }).collect();
+        let distribution = Distribution2D::new(&func, width, height);
+        let distribution = if distribution.integral() > 0.0 { Some(distribution) } else { None };
+        AmbientLight{image, rotation: 0.0, intensity: 1.0, distribution}
+    }
+
+    // rotates the environment counter clockwise around the z axis
+    pub fn with_rotation(self, degrees: f32) -> AmbientLight {
+        AmbientLight{rotation: degrees.to_radians(), ..self}
+    }
+
+    pub fn with_intensity(self, intensity: f32) -> AmbientLight {
+        AmbientLight{intensity, ..self}
     }
 
     pub fn get_color(&self, ray: &Ray) -> Color {
-        let x = ((ray.direction * Vec3n::new(1.0,0.0,0.0)) + 1.0) / 2.0 ;
-        //let y = (ray.direction * Vec3n::new(0.0,1.0,0.0));
-        let z = ((ray.direction * Vec3n::new(0.0,0.0,-1.0))+ 1.0) / 2.0 ;
-        self.image.get_at(x,z)
+        let (u, v) = self.direction_to_uv(&ray.direction);
+        ray.to_spectrum(self.image.bilinear(u, v, Wrap::Repeat, Wrap::Clamp) * self.intensity)
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.distribution.is_some()
+    }
+
+    // direction towards the environment, its radiance and pdf per solid angle
+    pub fn sample(&self, u: f32, v: f32) -> Option<(Vec3n, Color, f32)> {
+        let distribution = self.distribution.as_ref()?;
+        let ((x, y), pdf) = distribution.sample(u, v);
+        let sin_theta = f32::sin(PI * y);
+        if pdf <= 0.0 || sin_theta <= 0.0 { return None }
+        let direction = self.uv_to_direction(x, y);
+        let radiance = self.image.bilinear(x, y, Wrap::Repeat, Wrap::Clamp) * self.intensity;
+        Some((direction, radiance, pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    pub fn pdf(&self, direction: &Vec3n) -> f32 {
+        let Some(distribution) = &self.distribution else { return 0.0 };
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = f32::sin(PI * v);
+        if sin_theta <= 0.0 { return 0.0 }
+        distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn direction_to_uv(&self, direction: &Vec3n) -> (f32, f32) {
+        let d = Vec3::from(direction);
+        let phi = f32::atan2(d.y, d.x) - self.rotation;
+        // u grows to the right when looking at the environment from inside
+        let u = (0.5 - phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = f32::acos(d.z.clamp(-1.0, 1.0)) / PI;
+        (u.min(1.0 - f32::EPSILON), v)
+    }
+
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vec3n {
+        let phi = (0.5 - u) * 2.0 * PI + self.rotation;
+        let theta = v * PI;
+        Vec3n::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
     }
 }
 
-impl Image {
-    fn get_at(&self, u: f32, w: f32) -> Color {
-        assert!(u >= 0.0 && u <= 1.0);
-        assert!(w >= 0.0 && w <= 1.0);
-        let width = (u * self.resolution.width as f32) as usize;
-        let height = (w * self.resolution.height as f32) as usize;
-        self.get_data()[self.resolution.get_index(width, height)]
+impl Default for AmbientLight {
+    fn default() -> Self {
+        AmbientLight::new()
     }
 }
 
+// emits a constant radiance (W/(sr m²)) from the side the normal points to, or from both sides
 pub struct Emissive {
-    pub color: Color
+    pub radiance: Color,
+    pub two_sided: bool,
+}
+
+impl Emissive {
+    pub fn new(radiance: Color, two_sided: bool) -> Emissive {
+        Emissive{radiance, two_sided}
+    }
+
+    // radiance of a lambertian emitter with the given area that emits `power` watts in total,
+    // `color` only sets the tint and is normalized to unit luminance
+    pub fn from_power(color: Color, power: f32, area: f32, two_sided: bool) -> Emissive {
+        let luminance = color.luminance();
+        let sides = if two_sided { 2.0 } else { 1.0 };
+        let radiance = color / luminance.max(f32::MIN_POSITIVE) * (power / (sides * PI * area.max(f32::MIN_POSITIVE)));
+        Emissive{radiance, two_sided}
+    }
 }
 
 impl Shade for Emissive {
 
-    fn scatter(&self, intersection: &Intersection, ray: &Ray) -> Vec<ScatteredRay> {
-        let v = ray.origin - intersection.pos;
-        let attenuation = (1.0/v.len())*(1.0/v.len());
-        let color = attenuation*self.color;
-        vec![ScatteredRay::Absorbed{color}]
+    fn sample(&self, _intersection: &Intersection, _ray: &Ray) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        if self.two_sided || intersection.normal * ray.direction < 0.0 {
+            ray.to_spectrum(self.radiance)
+        } else {
+            Color::default()
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment() -> AmbientLight {
+        let mut image = Image::new(Resolution::new(8, 4));
+        image.get_data_mut().iter_mut().enumerate().for_each(|(i, c)| *c = Color::new(1.0 + i as f32));
+        AmbientLight::from_image(image).with_rotation(30.0)
+    }
+
+    // +z is the top row, the center column faces the rotated +x axis
+    #[test]
+    fn equirectangular_mapping() {
+        let light = environment();
+        let (u, v) = light.direction_to_uv(&Vec3n::new(0.0, 0.0, 1.0));
+        assert!(v.abs() < 1e-3, "{} {}", u, v);
+        let (u, v) = light.direction_to_uv(&light.uv_to_direction(0.3, 0.6));
+        assert!((u - 0.3).abs() < 1e-4 && (v - 0.6).abs() < 1e-4, "{} {}", u, v);
+        let forward = Vec3n::new(f32::to_radians(30.0).cos(), f32::to_radians(30.0).sin(), 0.0);
+        let (u, v) = light.direction_to_uv(&forward);
+        assert!((u - 0.5).abs() < 1e-4 && (v - 0.5).abs() < 1e-4, "{} {}", u, v);
+    }
+
+    // sampled directions report the same pdf and radiance as looking them up
+    #[test]
+    fn sample_matches_pdf() {
+        let light = environment();
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.33, 0.99)] {
+            let (direction, radiance, pdf) = light.sample(u, v).unwrap();
+            let ray = Ray::new(Vec3::default(), direction);
+            assert!((light.pdf(&direction) - pdf).abs() < 1e-3 * pdf, "{} != {}", light.pdf(&direction), pdf);
+            assert!((light.get_color(&ray).r - radiance.r).abs() < 1e-3 * radiance.r);
+        }
     }
 }
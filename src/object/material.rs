@@ -1,28 +1,63 @@
 use crate::color::Color;
 use crate::ray::{Intersection, Ray};
-use crate::object::{Shade, ScatteredRay};
+use crate::object::{Shade, BsdfSample, Lobe};
+use crate::object::microfacet::{Ggx, reflect, refract, fresnel_dielectric, eval_dielectric, local_outgoing};
 use crate::vec3::{Vec3, Vec3n};
 use crate::object::RAY_START_EPSILON;
+use crate::texture::Texture;
+use crate::spectrum::{Conductor, Ior, D_LINE};
+use crate::random::sample_rng;
 
-pub struct DiffuseSpecular {
-    pub diffuse: Color,
-    pub ambient: Color,
-    pub specular: Color,
-    pub shineness: f32
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+// lambertian reflection
+pub struct Diffuse {
+    pub diffuse: Arc<dyn Texture + Send + Sync>,
+}
+
+impl Diffuse {
+    pub fn new(diffuse: Arc<dyn Texture + Send + Sync>) -> Diffuse {
+        Diffuse{diffuse}
+    }
+}
+
+// the phong parameters it used to carry were never used by the path tracer
+#[deprecated(note = "renamed to Diffuse, construct it with Diffuse::new")]
+pub type DiffuseSpecular = Diffuse;
+
+// cosine weighted direction around the normal
+fn sample_cosine(normal: &Vec3n) -> Vec3n {
+    Vec3n::from(normal + Vec3n::random_sphere())
+}
+
+// continues `ray` from the intersection
+pub(crate) fn ray_towards(intersection: &Intersection, ray: &Ray, direction: Vec3n) -> Ray {
+    Ray{origin: intersection.pos + RAY_START_EPSILON * direction, direction, ..ray.clone()}
 }
 
-impl Shade for DiffuseSpecular {
+impl Shade for Diffuse {
+
+    // cosine weighted, so the weight is just the albedo
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        let normal = intersection.facing_normal(ray);
+        let direction = sample_cosine(&normal);
+        Some(BsdfSample{
+            weight: ray.to_spectrum(self.diffuse.value(intersection)),
+            ray: ray_towards(intersection, ray, direction),
+            pdf: Some(f32::max(0.0, normal * direction) / PI),
+            lobe: Lobe::Diffuse,
+        })
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        ray.to_spectrum(self.diffuse.value(intersection)) * (f32::max(0.0, intersection.facing_normal(ray) * direction) / PI)
+    }
 
-    fn scatter(&self, intersection: &Intersection, ray: &Ray) -> Vec<ScatteredRay> {
-        let direction= Vec3n::from(intersection.normal + Vec3n::random_sphere());
-        vec![ScatteredRay::Scattered{
-            attenuation: self.diffuse,
-            ray: Ray{
-                origin: intersection.pos + RAY_START_EPSILON*direction,
-                direction: direction,
-                inside: false,
-            }}
-        ]
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        f32::max(0.0, intersection.facing_normal(ray) * direction) / PI
     }
 }
 
@@ -36,93 +71,526 @@ fn calc_f0(n: &Color, k: &Color) -> Color {
 }
 
 pub struct Metalic {
-    f0: Color
+    f0: Arc<dyn Texture + Send + Sync>,
+    // measured n and k of the spectral mode, the rgb reflectance is used without them
+    conductor: Option<&'static Conductor>,
+    // None for a perfect mirror
+    distribution: Option<Ggx>,
 }
 
 impl Metalic {
     pub fn new(n: &Color, k: &Color) -> Metalic {
-        Metalic {
-            f0: calc_f0(n,k)
-        }
+        Metalic::from_f0(&calc_f0(n,k))
+    }
+
+    pub fn from_f0(f0: &Color) -> Metalic {
+        Metalic::from_texture(Arc::new(*f0))
+    }
+
+    // reflectance at normal incidence varying over the surface
+    pub fn from_texture(f0: Arc<dyn Texture + Send + Sync>) -> Metalic {
+        Metalic{f0, conductor: None, distribution: None}
+    }
+
+    // reflectance of measured n and k, per wavelength in spectral mode
+    pub fn from_conductor(conductor: &'static Conductor) -> Metalic {
+        Metalic{conductor: Some(conductor), ..Metalic::from_f0(&conductor.rgb_f0())}
     }
 
     pub fn gold() -> Metalic {
-        Metalic::new(
-            &Color{ r: 0.17, g:0.35, b: 1.5},
-            &Color{ r: 3.1, g: 2.7, b: 1.9},
-        )
+        Metalic::from_conductor(&Conductor::GOLD)
     }
 
     pub fn silver() -> Metalic {
-        Metalic::new(
-            &Color{ r: 0.14, g: 0.16, b: 0.13},
-            &Color{ r: 4.1, g: 2.3, b: 3.1},
-        )
+        Metalic::from_conductor(&Conductor::SILVER)
+    }
+
+    pub fn copper() -> Metalic {
+        Metalic::from_conductor(&Conductor::COPPER)
+    }
+
+    pub fn aluminium() -> Metalic {
+        Metalic::from_conductor(&Conductor::ALUMINIUM)
+    }
+
+    // brushed or blurry reflections, a roughness of 0 keeps the mirror
+    pub fn with_roughness(self, roughness: f32, anisotropy: f32) -> Metalic {
+        let distribution = (roughness > 0.0).then(|| Ggx::new(roughness, anisotropy));
+        Metalic{distribution, ..self}
+    }
+
+    // the measured n and k per wavelength in spectral mode, schlick's approximation of
+    // the rgb reflectance otherwise
+    fn fresnel(&self, intersection: &Intersection, ray: &Ray, cos_theta: f32) -> Color {
+        match (self.conductor, &ray.wavelengths) {
+            (Some(conductor), Some(wavelengths)) => wavelengths.map(|lambda| conductor.fresnel(lambda, cos_theta)),
+            _ => fresnel(ray.to_spectrum(self.f0.value(intersection)), cos_theta)
+        }
     }
 }
 
 impl Shade for Metalic {
-    fn scatter(&self, intersection: &Intersection, ray: &Ray) -> Vec<ScatteredRay> {
-        let direction = intersection.normal.reflect(&ray.direction);
-        vec![ScatteredRay::Scattered{
-            attenuation: fresnel(self.f0, -ray.direction * intersection.normal),
-            ray: Ray {
-                origin: intersection.pos + RAY_START_EPSILON * direction,
-                direction,
-                inside: false
-            }}
-        ]
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        let Some(ggx) = &self.distribution else {
+            let direction = intersection.normal.reflect(&ray.direction);
+            return Some(BsdfSample{
+                weight: self.fresnel(intersection, ray, -ray.direction * intersection.facing_normal(ray)),
+                ray: ray_towards(intersection, ray, direction),
+                pdf: None,
+                lobe: Lobe::Specular,
+            })
+        };
+
+        let (frame, wo) = local_outgoing(intersection, ray);
+        if wo.z <= 0.0 { return None }
+        let mut rng = sample_rng();
+        let h = ggx.sample_visible(&wo, rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        let wi = reflect(&wo, &h);
+        if wi.z <= 0.0 { return None }
+        Some(BsdfSample{
+            weight: self.fresnel(intersection, ray, wo * h) * (ggx.g(&wo, &wi) / ggx.g1(&wo)),
+            ray: ray_towards(intersection, ray, frame.to_world(&wi).norm()),
+            pdf: Some(ggx.pdf_visible(&wo, &h) / (4.0 * (wo * h))),
+            lobe: Lobe::Specular,
+        })
     }
-}
 
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        let Some(ggx) = &self.distribution else { return Color::default() };
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        if wo.z <= 0.0 || wi.z <= 0.0 { return Color::default() }
+        let h = (wo + wi).norm();
+        let h = Vec3::from(h);
+        self.fresnel(intersection, ray, wo * h) * (ggx.d(&h) * ggx.g(&wo, &wi) / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        let Some(ggx) = &self.distribution else { return 0.0 };
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        if wo.z <= 0.0 || wi.z <= 0.0 { return 0.0 }
+        let h = Vec3::from((wo + wi).norm());
+        ggx.pdf_visible(&wo, &h) / (4.0 * (wo * h))
+    }
+}
 
 pub struct Refractive {
-    f0: Color,
-    n: f32,
-    n_rec: f32,
+    ior: Ior,
+    // None for clear glass
+    distribution: Option<Ggx>,
 }
 
 impl Refractive {
     pub fn new(n: &Color) -> Refractive {
-        Refractive {
-            f0: calc_f0(n, n),
-            n: n.r,
-            n_rec: 1.0 / n.r,
-        }
+        Refractive::from_ior(Ior::Constant(n.r))
+    }
+
+    // dispersive glass splits light into its colors in spectral mode
+    pub fn from_ior(ior: Ior) -> Refractive {
+        Refractive{ior, distribution: None}
     }
 
     pub fn glass() -> Refractive {
         Refractive::new(&Color::new(1.5))
     }
+
+    // frosted glass, a roughness of 0 keeps it clear
+    pub fn with_roughness(self, roughness: f32) -> Refractive {
+        let distribution = (roughness > 0.0).then(|| Ggx::new(roughness, 0.0));
+        Refractive{distribution, ..self}
+    }
+
+    // index of refraction at the hero wavelength
+    fn n(&self, ray: &Ray) -> f32 {
+        self.ior.at(ray.wavelengths.map_or(D_LINE, |wavelengths| wavelengths.hero()))
+    }
+
+    // ratio of the index of refraction behind the surface to the one in front of it, the
+    // side is the one of the actual surface like for the media
+    fn eta(intersection: &Intersection, ray: &Ray, n: f32) -> f32 {
+        if intersection.from_inside(ray) { 1.0 / n } else { n }
+    }
+
+    // bsdf times cosine per wavelength and pdf of the hero wavelength
+    fn eval_rough(&self, ggx: &Ggx, intersection: &Intersection, wo: &Vec3, wi: &Vec3, ray: &Ray) -> (Color, f32) {
+        let Some((value, pdf)) = eval_dielectric(ggx, wo, wi, Refractive::eta(intersection, ray, self.n(ray))) else { return (Color::default(), 0.0) };
+        match &ray.wavelengths {
+            Some(wavelengths) if self.ior.is_dispersive() => (wavelengths.map(|lambda| {
+                eval_dielectric(ggx, wo, wi, Refractive::eta(intersection, ray, self.ior.at(lambda))).map_or(0.0, |(value, _)| value)
+            }), pdf),
+            _ => (Color::new(value), pdf)
+        }
+    }
 }
 
 impl Shade for Refractive {
-    fn scatter(&self, intersection: &Intersection, ray: &Ray) -> Vec<ScatteredRay>  {
-        let normal = if !ray.inside { intersection.normal } else { - intersection.normal };
-        //TODO: feels like this is a bug, and should be swapped, but then the whole thing breaks..
-        let n = if ray.inside {self.n} else {self.n_rec};
+    // reflects or refracts with the probability of the fresnel term
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        if let Some(ggx) = &self.distribution {
+            return self.sample_rough(ggx, intersection, ray)
+        }
+
+        // the other wavelengths would take different directions, only the hero one continues
+        let (ray, dispersion) = match ray.wavelengths {
+            Some(wavelengths) if self.ior.is_dispersive() => {
+                let (wavelengths, weight) = wavelengths.disperse();
+                (&Ray{wavelengths: Some(wavelengths), ..ray.clone()}, weight)
+            },
+            _ => (ray, Color::new(1.0))
+        };
+
+        let normal = intersection.facing_normal(ray);
+        let eta = Refractive::eta(intersection, ray, self.n(ray));
 
         let reflected_direction = normal.reflect(&ray.direction);
-        let reflected_ray = Ray {
-            direction: reflected_direction,
-            origin: intersection.pos + RAY_START_EPSILON * reflected_direction,
-            inside: ray.inside
+        let reflected_ray = ray_towards(intersection, ray, reflected_direction);
+
+        if let Some(refracted_direction) = normal.refract(&ray.direction, 1.0 / eta) {
+            // reflects with the probability of the fresnel term, so both weights cancel to one
+            if sample_rng().gen_range(0.0..1.0) < fresnel_dielectric(-ray.direction * normal, eta) {
+                Some(BsdfSample{weight: dispersion, ray: reflected_ray, pdf: None, lobe: Lobe::Specular})
+            } else {
+                let refracted_ray = ray_towards(intersection, ray, refracted_direction);
+                Some(BsdfSample{weight: dispersion, ray: refracted_ray, pdf: None, lobe: Lobe::Transmission})
+            }
+        } else {
+            Some(BsdfSample{weight: dispersion, ray: reflected_ray, pdf: None, lobe: Lobe::Specular})
+        }
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        let Some(ggx) = &self.distribution else { return Color::default() };
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        self.eval_rough(ggx, intersection, &wo, &wi, ray).0
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        let Some(ggx) = &self.distribution else { return 0.0 };
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        eval_dielectric(ggx, &wo, &wi, Refractive::eta(intersection, ray, self.n(ray))).map_or(0.0, |(_, pdf)| pdf)
+    }
+}
+
+impl Refractive {
+    fn sample_rough(&self, ggx: &Ggx, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        let (frame, wo) = local_outgoing(intersection, ray);
+        if wo.z <= 0.0 { return None }
+        let eta = Refractive::eta(intersection, ray, self.n(ray));
+        let mut rng = sample_rng();
+        let h = ggx.sample_visible(&wo, rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        let f = fresnel_dielectric(wo * h, eta);
+
+        let (wi, lobe) = if rng.gen_range(0.0..1.0) < f {
+            (reflect(&wo, &h), Lobe::Specular)
+        } else {
+            (refract(&wo, &h, eta)?, Lobe::Transmission)
         };
+        // reflections off steep microfacets can point below the surface, eval would take
+        // them for transmissions
+        if (wi.z < 0.0) != (lobe == Lobe::Transmission) { return None }
+        let (value, pdf) = self.eval_rough(ggx, intersection, &wo, &wi, ray);
+        if pdf <= 0.0 { return None }
+        Some(BsdfSample{
+            weight: value / pdf,
+            ray: ray_towards(intersection, ray, frame.to_world(&wi).norm()),
+            pdf: Some(pdf),
+            lobe,
+        })
+    }
+}
+
+// diffuse base under a smooth or rough dielectric coat
+pub struct Plastic {
+    diffuse: Arc<dyn Texture + Send + Sync>,
+    ior: f32,
+    distribution: Ggx,
+}
 
-        if let Some(refracted_direction) = normal.refract(&ray.direction, n) {
-            let refracted_ray = Ray {
-                direction: refracted_direction,
-                origin: intersection.pos + RAY_START_EPSILON * refracted_direction,
-                inside: !ray.inside
-            };
-
-            let f = fresnel(self.f0, -ray.direction * normal);
-            vec![
-                ScatteredRay::Scattered{attenuation: f, ray: reflected_ray},
-                ScatteredRay::Scattered{attenuation: Color::new(1.0) -f, ray: refracted_ray}
-            ]
+impl Plastic {
+    pub fn new(diffuse: Arc<dyn Texture + Send + Sync>, ior: f32, roughness: f32) -> Plastic {
+        Plastic{diffuse, ior, distribution: Ggx::new(roughness, 0.0)}
+    }
+
+    // probability of sampling the coat instead of the base
+    fn coat_probability(&self, wo: &Vec3) -> f32 {
+        fresnel_dielectric(wo.z, self.ior).clamp(0.1, 0.9)
+    }
+
+    fn eval_local(&self, diffuse: Color, wo: &Vec3, wi: &Vec3) -> (Color, f32) {
+        if wo.z <= 0.0 || wi.z <= 0.0 { return (Color::default(), 0.0) }
+        let ggx = &self.distribution;
+        let h = Vec3::from((wo + wi).norm());
+        let coat = fresnel_dielectric(wo * h, self.ior) * ggx.d(&h) * ggx.g(wo, wi) / (4.0 * wo.z);
+        // light reaching the base passes the coat twice
+        let transmitted = (1.0 - fresnel_dielectric(wo.z, self.ior)) * (1.0 - fresnel_dielectric(wi.z, self.ior));
+        let value = Color::new(coat) + diffuse * (transmitted * wi.z / PI);
+
+        let p = self.coat_probability(wo);
+        let pdf = p * ggx.pdf_visible(wo, &h) / (4.0 * (wo * h)) + (1.0 - p) * wi.z / PI;
+        (value, pdf)
+    }
+}
+
+impl Shade for Plastic {
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        let (frame, wo) = local_outgoing(intersection, ray);
+        if wo.z <= 0.0 { return None }
+        let mut rng = sample_rng();
+        let (wi, lobe) = if rng.gen_range(0.0..1.0) < self.coat_probability(&wo) {
+            let h = self.distribution.sample_visible(&wo, rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+            (reflect(&wo, &h), Lobe::Specular)
         } else {
-            vec![ScatteredRay::Scattered{attenuation: Color::new(1.0), ray: reflected_ray}]
+            (Vec3::from(sample_cosine(&Vec3n::new(0.0, 0.0, 1.0))), Lobe::Diffuse)
+        };
+        let (value, pdf) = self.eval_local(ray.to_spectrum(self.diffuse.value(intersection)), &wo, &wi);
+        if pdf <= 0.0 { return None }
+        Some(BsdfSample{
+            weight: value / pdf,
+            ray: ray_towards(intersection, ray, frame.to_world(&wi).norm()),
+            pdf: Some(pdf),
+            lobe,
+        })
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        self.eval_local(ray.to_spectrum(self.diffuse.value(intersection)), &wo, &wi).0
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        self.eval_local(Color::default(), &wo, &wi).1
+    }
+}
+
+// the boundary of a medium that has no surface of its own, like smoke or fog in a box
+pub struct Interface;
+
+impl Shade for Interface {
+    // the path tracer steps through interfaces without sampling them, light continues
+    // straight on and the side of the ray does not change
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        Some(BsdfSample{
+            ray: Ray{origin: intersection.pos + RAY_START_EPSILON * ray.direction, ..ray.clone()},
+            weight: Color::new(1.0),
+            pdf: None,
+            lobe: Lobe::Transmission,
+        })
+    }
+
+    fn is_interface(&self) -> bool {
+        true
+    }
+}
+
+// replaces the shading normal before the wrapped material sees the intersection
+pub enum Perturbation {
+    // tangent space normals encoded as rgb in [0, 1], the texture must not be sRGB decoded
+    NormalMap(Arc<dyn Texture + Send + Sync>),
+    // the luminance of the texture is a height, `strength` scales its slope
+    Bump{height: Arc<dyn Texture + Send + Sync>, strength: f32},
+}
+
+pub struct Perturbed {
+    material: Box<dyn Shade + Sync>,
+    perturbation: Perturbation,
+}
+
+// offsets for the finite differences of bump maps. solid height textures are shifted along
+// the surface in world units, all others only see the uv coordinates, which span the whole
+// texture in 0..1, a fraction of a texel of a large image
+const BUMP_DELTA_WORLD: f32 = 1e-3;
+const BUMP_DELTA_UV: f32 = 1e-4;
+
+impl Perturbed {
+    pub fn new(material: Box<dyn Shade + Sync>, perturbation: Perturbation) -> Perturbed {
+        Perturbed{material, perturbation}
+    }
+
+    fn perturb(&self, intersection: &Intersection, ray: &Ray) -> Intersection {
+        let (n, t, b) = (Vec3::from(intersection.normal), Vec3::from(intersection.tangent), Vec3::from(intersection.bitangent));
+        let normal = match &self.perturbation {
+            Perturbation::NormalMap(normals) => {
+                let c = normals.value(intersection);
+                (2.0 * c.r - 1.0) * t + (2.0 * c.g - 1.0) * b + (2.0 * c.b - 1.0) * n
+            },
+            Perturbation::Bump{height, strength} => {
+                let solid = height.is_solid();
+                let delta = if solid { BUMP_DELTA_WORLD } else { BUMP_DELTA_UV };
+                let height_at = |du: f32, dv: f32| {
+                    let shifted = if solid {
+                        Intersection{pos: intersection.pos + du * t + dv * b, ..*intersection}
+                    } else {
+                        Intersection{uv: (intersection.uv.0 + du, intersection.uv.1 + dv), ..*intersection}
+                    };
+                    height.value(&shifted).luminance()
+                };
+                let h = height_at(0.0, 0.0);
+                let dhdu = (height_at(delta, 0.0) - h) / delta;
+                let dhdv = (height_at(0.0, delta) - h) / delta;
+                n - (strength * dhdu) * t - (strength * dhdv) * b
+            }
+        };
+        if normal.len_squared() <= 0.0 { return *intersection }
+        let normal = normal.norm();
+
+        // a normal tilted away from the viewer would reflect into the surface
+        let incoming = Vec3::from(ray.direction);
+        if (normal * incoming) * (intersection.normal * incoming) <= 0.0 { return *intersection }
+
+        // keeps the handedness of the frame it was given
+        let (tangent, bitangent) = normal.tangent_frame(&t);
+        let bitangent = if Vec3::from(bitangent) * b < 0.0 { -bitangent } else { bitangent };
+        Intersection{normal, tangent, bitangent, ..*intersection}
+    }
+}
+
+impl Shade for Perturbed {
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        self.material.sample(&self.perturb(intersection, ray), ray)
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        self.material.eval(&self.perturb(intersection, ray), ray, direction)
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        self.material.pdf(&self.perturb(intersection, ray), ray, direction)
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        self.material.emitted(intersection, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.material.is_interface()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // heights rising along u or along the world x axis
+    struct Ramp(bool);
+
+    impl Texture for Ramp {
+        fn value(&self, intersection: &Intersection) -> Color {
+            Color::new(if self.0 { intersection.pos.x } else { intersection.uv.0 })
         }
+
+        fn is_solid(&self) -> bool {
+            self.0
+        }
+    }
+
+    fn perturbed(perturbation: Perturbation) -> Perturbed {
+        Perturbed::new(Box::new(Diffuse::new(Arc::new(Color::new(0.5)))), perturbation)
     }
-}
\ No newline at end of file
+
+    // a surface facing up with u along x, seen from above
+    fn flat() -> (Intersection, Ray) {
+        let intersection = Intersection::new(Vec3{x: 0.3, y: 0.2, z: 0.0}, Vec3n::new(0.0, 0.0, 1.0), &Vec3{x: 1.0, y: 0.0, z: 0.0}, (0.3, 0.2), 1.0);
+        (intersection, Ray::new(Vec3{x: 0.0, y: 0.0, z: 1.0}, Vec3n::new(0.3, 0.2, -1.0)))
+    }
+
+    #[test]
+    fn flat_perturbations_keep_the_normal() {
+        let (intersection, ray) = flat();
+        // constant colors are solid, checkers are mapped by uv
+        let checker = Arc::new(crate::texture::Checker::new(Arc::new(Color::new(0.7)), Arc::new(Color::new(0.7)), 4.0));
+        for perturbation in [
+            Perturbation::Bump{height: Arc::new(Color::new(0.7)), strength: 5.0},
+            Perturbation::Bump{height: checker, strength: 5.0},
+            Perturbation::NormalMap(Arc::new(Color{r: 0.5, g: 0.5, b: 1.0})),
+        ] {
+            let perturbed = perturbed(perturbation).perturb(&intersection, &ray);
+            assert!((Vec3::from(perturbed.normal) - Vec3::from(intersection.normal)).len() < 1e-5, "{:?}", perturbed.normal);
+            assert!((Vec3::from(perturbed.tangent) - Vec3::from(intersection.tangent)).len() < 1e-5);
+        }
+    }
+
+    // the same slope per uv unit and per world unit tilts the normal the same way, against it
+    #[test]
+    fn bump_slopes() {
+        let (intersection, ray) = flat();
+        for solid in [false, true] {
+            let perturbed = perturbed(Perturbation::Bump{height: Arc::new(Ramp(solid)), strength: 1.0}).perturb(&intersection, &ray);
+            let expected = Vec3::from(Vec3n::new(-1.0, 0.0, 1.0));
+            assert!((Vec3::from(perturbed.normal) - expected).len() < 1e-2, "{:?} for solid {}", perturbed.normal, solid);
+            assert!(perturbed.tangent * perturbed.normal < 1e-5 && perturbed.bitangent * perturbed.normal < 1e-5);
+        }
+    }
+
+    // a normal that would face away from the viewer is not used
+    #[test]
+    fn perturbations_facing_away_are_ignored() {
+        let (intersection, _) = flat();
+        let grazing = Ray::new(Vec3{x: -1.0, y: 0.0, z: 0.1}, Vec3n::new(1.0, 0.0, -0.1));
+        let perturbed = perturbed(Perturbation::NormalMap(Arc::new(Color{r: 1.0, g: 0.5, b: 0.55}))).perturb(&intersection, &grazing);
+        assert!((Vec3::from(perturbed.normal) - Vec3::from(intersection.normal)).len() == 0.0);
+    }
+
+    // clear glass reflects 4% at normal incidence and everything past the critical angle
+    #[test]
+    fn smooth_glass_fresnel() {
+        crate::random::seed(1);
+        let intersection = Intersection::new(Vec3::default(), Vec3n::new(0.0, 0.0, 1.0), &Vec3{x: 1.0, y: 0.0, z: 0.0}, (0.0, 0.0), 1.0);
+        let reflected = |ray: &Ray, n: usize| (0..n)
+            .filter(|_| Refractive::glass().sample(&intersection, ray).unwrap().lobe == Lobe::Specular).count() as f32 / n as f32;
+        let down = Ray::new(Vec3{x: 0.0, y: 0.0, z: 1.0}, Vec3n::new(0.0, 0.0, -1.0));
+        let f = reflected(&down, 20000);
+        assert!((f - 0.04).abs() < 0.01, "{} != 0.04", f);
+        let grazing_inside = Ray::new(Vec3{x: 0.0, y: 0.0, z: -1.0}, Vec3n::new(1.0, 0.0, 0.5));
+        assert_eq!(reflected(&grazing_inside, 100), 1.0);
+    }
+
+    // the weights of the sampled directions have to average to the albedo found by evaluating
+    // the bsdf in all directions, which fails if the pdf is not the density the directions
+    // are sampled with or samples end up on the other side than their lobe says
+    #[test]
+    fn rough_glass_samples_match_eval() {
+        use crate::object::principled::Principled;
+        let value = |v: f32| Arc::new(Color::new(v)) as Arc<dyn Texture + Send + Sync>;
+        let principled = Principled{transmission: value(1.0), roughness: value(0.5), ..Principled::new(value(1.0))};
+        let materials: [Box<dyn Shade + Sync>; 2] = [Box::new(Refractive::glass().with_roughness(0.5)), Box::new(principled)];
+        let intersection = Intersection::new(Vec3::default(), Vec3n::new(0.0, 0.0, 1.0), &Vec3{x: 1.0, y: 0.0, z: 0.0}, (0.0, 0.0), 1.0);
+        crate::random::seed(1);
+        let n = 40000;
+        for (glass, inside) in materials.iter().flat_map(|m| [(m, false), (m, true)]) {
+            for cos in [0.9f32, 0.4] {
+                // from above the surface or from inside the glass below it
+                let direction = Vec3n::new(-f32::sqrt(1.0 - cos * cos), 0.0, if inside { cos } else { -cos });
+                let ray = Ray::new(-1.0 * Vec3::from(direction), direction);
+
+                let sampled = (0..n).filter_map(|_| glass.sample(&intersection, &ray)).map(|s| s.weight.r).sum::<f32>() / n as f32;
+                // uniform directions, stratified by height and angle
+                let (mut evaluated, mut pdf) = (0.0, 0.0);
+                let steps = 200;
+                let mut rng = sample_rng();
+                for i in 0..steps {
+                    for j in 0..steps {
+                        let z = 2.0 * (i as f32 + rng.gen_range(0.0..1.0)) / steps as f32 - 1.0;
+                        let phi = 2.0 * PI * (j as f32 + rng.gen_range(0.0..1.0)) / steps as f32;
+                        let r = f32::sqrt(1.0 - z * z);
+                        let direction = Vec3n::new(r * phi.cos(), r * phi.sin(), z);
+                        let weight = 4.0 * PI / (steps * steps) as f32;
+                        evaluated += glass.eval(&intersection, &ray, &direction).r * weight;
+                        pdf += glass.pdf(&intersection, &ray, &direction) * weight;
+                    }
+                }
+                assert!(pdf < 1.02, "pdf integrates to {} inside {} cos {}", pdf, inside, cos);
+                assert!((sampled - evaluated).abs() < 0.02, "{} != {} inside {} cos {}", sampled, evaluated, inside, cos);
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::vec3::{Vec3, Vec3n};
+use crate::object::{Object, Intersect, Shade, BsdfSample};
+use crate::ray::{Ray, Intersection};
+use crate::color::Color;
+use crate::bvh::{Aabb, Bvh};
+use crate::medium::Medium;
+use crate::random::sample_rng;
+
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+const TRIANGLE_EPSILON: f32 = 1e-7;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub pos: Vec3,
+    pub normal: Option<Vec3n>,
+    pub uv: (f32, f32),
+}
+
+#[derive(Clone)]
+pub struct Triangle {
+    vertices: [Vertex; 3],
+    normal: Vec3n,
+    // direction of increasing u and whether v increases along normal x tangent
+    dpdu: Vec3,
+    right_handed: bool,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Vertex; 3]) -> Triangle {
+        let [a, b, c] = &vertices;
+        let (edge1, edge2) = (b.pos - a.pos, c.pos - a.pos);
+        let normal = edge1.cross(&edge2).norm();
+        let (du1, dv1) = (b.uv.0 - a.uv.0, b.uv.1 - a.uv.1);
+        let (du2, dv2) = (c.uv.0 - a.uv.0, c.uv.1 - a.uv.1);
+        let det = du1 * dv2 - du2 * dv1;
+        let (dpdu, right_handed) = if det.abs() > 1e-12 {
+            let dpdu = (dv2 * edge1 - dv1 * edge2) / det;
+            let dpdv = (du1 * edge2 - du2 * edge1) / det;
+            (dpdu, Vec3::from(normal).cross(&dpdu) * dpdv >= 0.0)
+        } else {
+            (edge1, true)
+        };
+        Triangle{vertices, normal, dpdu, right_handed}
+    }
+
+    // degenerate triangles have no area and can never be hit
+    pub fn is_degenerate(&self) -> bool {
+        self.area() == 0.0
+    }
+
+    pub fn area(&self) -> f32 {
+        let [a, b, c] = &self.vertices;
+        (b.pos - a.pos).cross(&(c.pos - a.pos)).len() / 2.0
+    }
+
+    // uniform point from two random numbers in [0, 1)
+    pub fn sample(&self, u: f32, v: f32) -> Intersection {
+        let [a, b, c] = &self.vertices;
+        let su = u.sqrt();
+        let pos = (1.0 - su) * a.pos + su * (1.0 - v) * b.pos + su * v * c.pos;
+        self.intersection(pos, su * (1.0 - v), su * v, 0.0)
+    }
+
+    // the surface at `pos` with barycentric coordinates `u` and `v` of the second and third vertex
+    fn intersection(&self, pos: Vec3, u: f32, v: f32, t: f32) -> Intersection {
+        let [a, b, c] = &self.vertices;
+        let w = 1.0 - u - v;
+        let normal = match (a.normal, b.normal, c.normal) {
+            (Some(na), Some(nb), Some(nc)) => {
+                let n = w * na + u * nb + v * nc;
+                if n.len_squared() > 0.0 { n.norm() } else { self.normal }
+            },
+            _ => self.normal
+        };
+        let uv = (
+            w * a.uv.0 + u * b.uv.0 + v * c.uv.0,
+            w * a.uv.1 + u * b.uv.1 + v * c.uv.1,
+        );
+        let mut intersection = Intersection::new(pos, normal, &self.dpdu, uv, t);
+        intersection.geometric_normal = if self.normal * normal < 0.0 { -self.normal } else { self.normal };
+        if !self.right_handed { intersection.bitangent = -intersection.bitangent }
+        intersection
+    }
+}
+
+impl Intersect for Triangle {
+    // Möller–Trumbore
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let [a, b, c] = &self.vertices;
+        let direction = Vec3::from(ray.direction);
+        let edge1 = b.pos - a.pos;
+        let edge2 = c.pos - a.pos;
+        let p = direction.cross(&edge2);
+        let det = edge1 * p;
+        if det.abs() < TRIANGLE_EPSILON { return None }
+
+        let inv_det = 1.0 / det;
+        let s = ray.origin - a.pos;
+        let u = (s * p) * inv_det;
+        if !(0.0..=1.0).contains(&u) { return None }
+
+        let q = s.cross(&edge1);
+        let v = (direction * q) * inv_det;
+        if v < 0.0 || u + v > 1.0 { return None }
+
+        let t = (edge2 * q) * inv_det;
+        if t <= 0.0 { return None }
+
+        Some(self.intersection(ray.at(t), u, v, t))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let [a, b, c] = &self.vertices;
+        Some(Aabb::new(a.pos, a.pos).grow(&b.pos).grow(&c.pos))
+    }
+}
+
+// the triangles of a mesh with their bvh, shared by all meshes placed from it
+pub struct Geometry {
+    triangles: Vec<Triangle>,
+    bvh: Bvh,
+    bounds: Aabb,
+    // cumulative triangle areas for sampling the surface
+    areas: Vec<f32>,
+}
+
+impl Geometry {
+    pub fn new(triangles: Vec<Triangle>) -> Geometry {
+        let triangles: Vec<Triangle> = triangles.into_iter().filter(|t| !t.is_degenerate()).collect();
+        let bounds: Vec<_> = triangles.iter().enumerate().map(|(i, t)| (i, t.bounds().unwrap())).collect();
+        let bvh = Bvh::new(&bounds);
+        let bounds = bounds.iter().fold(Aabb::empty(), |b, (_, t)| b.union(t));
+        let areas = triangles.iter().scan(0.0, |sum, t| { *sum += t.area(); Some(*sum) }).collect();
+        Geometry{triangles, bvh, bounds, areas}
+    }
+
+    pub fn area(&self) -> f32 {
+        self.areas.last().copied().unwrap_or(0.0)
+    }
+}
+
+pub struct Mesh {
+    geometry: Arc<Geometry>,
+    material: Box::<dyn Shade + Sync>,
+    medium: Option<Arc<dyn Medium + Send + Sync>>,
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<Triangle>, material: Box::<dyn Shade + Sync>) -> Mesh {
+        Mesh::shared(Arc::new(Geometry::new(triangles)), material)
+    }
+
+    // places the same triangles again without copying them, usually inside an `Instance`
+    pub fn shared(geometry: Arc<Geometry>, material: Box::<dyn Shade + Sync>) -> Mesh {
+        Mesh{geometry, material, medium: None}
+    }
+
+    // only meaningful for closed meshes with consistently wound triangles
+    pub fn with_medium(self, medium: Arc<dyn Medium + Send + Sync>) -> Mesh {
+        Mesh{medium: Some(medium), ..self}
+    }
+}
+
+impl Intersect for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let geometry = &self.geometry;
+        geometry.bvh.traverse(ray, |i| geometry.triangles[i].intersect(ray)).map(|(_, intersection)| intersection)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        if self.geometry.triangles.is_empty() { None } else { Some(self.geometry.bounds) }
+    }
+
+    fn area(&self) -> f32 {
+        self.geometry.area()
+    }
+
+    fn sample_surface(&self, _time: f32) -> Option<Intersection> {
+        let mut rng = sample_rng();
+        let target = rng.gen_range(0.0..1.0) * self.area();
+        let idx = self.geometry.areas.partition_point(|&a| a < target);
+        self.geometry.triangles.get(idx).map(|t| t.sample(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)))
+    }
+}
+
+impl Shade for Mesh {
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        self.material.sample(intersection, ray)
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        self.material.emitted(intersection, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.material.is_interface()
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        self.material.eval(intersection, ray, direction)
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        self.material.pdf(intersection, ray, direction)
+    }
+}
+
+impl Object for Mesh {
+    fn medium(&self) -> Option<&(dyn Medium + Send + Sync)> {
+        self.medium.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a right triangle in the xy plane facing up, uv follow x and y
+    fn triangle(normals: Option<[Vec3n; 3]>) -> Triangle {
+        let vertex = |i: usize, x: f32, y: f32| Vertex{pos: Vec3{x, y, z: 0.0}, normal: normals.map(|n| n[i]), uv: (x, y)};
+        Triangle::new([vertex(0, 0.0, 0.0), vertex(1, 1.0, 0.0), vertex(2, 0.0, 1.0)])
+    }
+
+    fn down(x: f32, y: f32) -> Ray {
+        Ray::new(Vec3{x, y, z: 2.0}, Vec3n::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let triangle = triangle(None);
+        let hit = triangle.intersect(&down(0.25, 0.5)).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-6);
+        assert!((hit.pos - Vec3{x: 0.25, y: 0.5, z: 0.0}).len() < 1e-6);
+        assert!((hit.uv.0 - 0.25).abs() < 1e-6 && (hit.uv.1 - 0.5).abs() < 1e-6);
+        assert!((Vec3::from(hit.normal) - Vec3{x: 0.0, y: 0.0, z: 1.0}).len() < 1e-6);
+        assert!((Vec3::from(hit.tangent) - Vec3{x: 1.0, y: 0.0, z: 0.0}).len() < 1e-6);
+        // from below the back face is hit as well
+        let up = Ray::new(Vec3{x: 0.25, y: 0.25, z: -1.0}, Vec3n::new(0.0, 0.0, 1.0));
+        assert!((triangle.intersect(&up).unwrap().t - 1.0).abs() < 1e-6);
+
+        // past the hypotenuse and each leg
+        for (x, y) in [(0.6, 0.6), (-0.1, 0.5), (0.5, -0.1)] {
+            assert!(triangle.intersect(&down(x, y)).is_none(), "{} {}", x, y);
+        }
+        // behind the origin and parallel to the plane
+        let behind = Ray::new(Vec3{x: 0.25, y: 0.25, z: -1.0}, Vec3n::new(0.0, 0.0, -1.0));
+        let parallel = Ray::new(Vec3{x: -1.0, y: 0.25, z: 0.0}, Vec3n::new(1.0, 0.0, 0.0));
+        assert!(triangle.intersect(&behind).is_none());
+        assert!(triangle.intersect(&parallel).is_none());
+    }
+
+    // rays through vertices and edges hit, shared edges leave no gaps
+    #[test]
+    fn edges() {
+        let triangle = triangle(None);
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.5, 0.0), (0.0, 0.5), (0.5, 0.5)] {
+            assert!(triangle.intersect(&down(x, y)).is_some(), "{} {}", x, y);
+        }
+        let other = Triangle::new([
+            Vertex{pos: Vec3{x: 1.0, y: 0.0, z: 0.0}, normal: None, uv: (0.0, 0.0)},
+            Vertex{pos: Vec3{x: 1.0, y: 1.0, z: 0.0}, normal: None, uv: (0.0, 0.0)},
+            Vertex{pos: Vec3{x: 0.0, y: 1.0, z: 0.0}, normal: None, uv: (0.0, 0.0)},
+        ]);
+        for i in 0..=16 {
+            let s = i as f32 / 16.0;
+            let ray = down(s, 1.0 - s);
+            assert!(triangle.intersect(&ray).is_some() || other.intersect(&ray).is_some(), "{}", s);
+        }
+    }
+
+    // vertex normals are interpolated by the barycentric coordinates, the geometric normal
+    // stays on their side
+    #[test]
+    fn interpolated_normals() {
+        let up = Vec3n::new(0.0, 0.0, 1.0);
+        let tilted = Vec3n::new(1.0, 0.0, 1.0);
+        let hit = triangle(Some([up, tilted, up])).intersect(&down(0.5, 0.25)).unwrap();
+        let expected = Vec3n::from(0.5 * up + 0.5 * tilted);
+        assert!((hit.normal - expected).len() < 1e-5, "{:?}", hit.normal);
+        assert!((Vec3::from(hit.geometric_normal) - Vec3::from(up)).len() < 1e-6);
+
+        let down_normals = [-up, -up, -up];
+        let hit = triangle(Some(down_normals)).intersect(&down(0.25, 0.25)).unwrap();
+        assert!(hit.geometric_normal * hit.normal > 0.0);
+    }
+}
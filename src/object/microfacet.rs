@@ -0,0 +1,164 @@
+use crate::vec3::{Vec3, Vec3n};
+use crate::ray::{Intersection, Ray};
+
+use std::f32::consts::PI;
+
+// smallest roughness, below it the distribution gets numerically unstable
+const MIN_ALPHA: f32 = 1e-3;
+
+// anisotropic GGX (Trowbridge-Reitz) distribution of microfacet normals, in the local
+// frame with x along the tangent and z along the normal
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    // perceptual roughness in [0, 1], anisotropy in [0, 1) stretches the highlight along the tangent
+    pub fn new(roughness: f32, anisotropy: f32) -> Ggx {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        let aspect = f32::sqrt(1.0 - 0.9 * anisotropy.clamp(0.0, 1.0));
+        Ggx{alpha_x: f32::max(MIN_ALPHA, alpha / aspect), alpha_y: f32::max(MIN_ALPHA, alpha * aspect)}
+    }
+
+    pub fn d(&self, h: &Vec3) -> f32 {
+        if h.z <= 0.0 { return 0.0 }
+        let (x, y) = (h.x / self.alpha_x, h.y / self.alpha_y);
+        let e = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f32 {
+        let (x, y) = (w.x * self.alpha_x, w.y * self.alpha_y);
+        let tan2 = (x * x + y * y) / (w.z * w.z);
+        if !tan2.is_finite() { return f32::INFINITY }
+        (f32::sqrt(1.0 + tan2) - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // height correlated masking and shadowing
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // samples a microfacet normal visible from `wo` (Heitz 2018)
+    pub fn sample_visible(&self, wo: &Vec3, u1: f32, u2: f32) -> Vec3 {
+        let v = Vec3{x: self.alpha_x * wo.x, y: self.alpha_y * wo.y, z: wo.z};
+        let v = v / v.len();
+        let len2 = v.x * v.x + v.y * v.y;
+        let t1 = if len2 > 0.0 { Vec3{x: -v.y, y: v.x, z: 0.0} / len2.sqrt() } else { Vec3{x: 1.0, y: 0.0, z: 0.0} };
+        let t2 = v.cross(&t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * f32::sqrt(1.0 - p1 * p1) + s * r * phi.sin();
+        let p3 = f32::sqrt(f32::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+        let n = p1 * t1 + p2 * t2 + p3 * v;
+
+        let h = Vec3{x: self.alpha_x * n.x, y: self.alpha_y * n.y, z: f32::max(1e-6, n.z)};
+        h / h.len()
+    }
+
+    // pdf of `sample_visible` returning `h`
+    pub fn pdf_visible(&self, wo: &Vec3, h: &Vec3) -> f32 {
+        if wo.z <= 0.0 { return 0.0 }
+        self.g1(wo) * f32::max(0.0, wo * h) * self.d(h) / wo.z
+    }
+}
+
+// orthonormal frame of the shading normal at an intersection
+pub struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl Frame {
+    pub fn new(intersection: &Intersection, normal: &Vec3n) -> Frame {
+        let normal = Vec3::from(normal);
+        let (tangent, bitangent) = if normal * Vec3::from(intersection.normal) < 0.0 {
+            // flipped to the other side, keep the frame right handed
+            (Vec3::from(intersection.tangent), -1.0 * Vec3::from(intersection.bitangent))
+        } else {
+            (Vec3::from(intersection.tangent), Vec3::from(intersection.bitangent))
+        };
+        Frame{tangent, bitangent, normal}
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3{x: v * self.tangent, y: v * self.bitangent, z: v * self.normal}
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+    }
+}
+
+// the outgoing direction `wo` (towards the origin of the ray) in the frame of the facing normal
+pub fn local_outgoing(intersection: &Intersection, ray: &Ray) -> (Frame, Vec3) {
+    let frame = Frame::new(intersection, &intersection.facing_normal(ray));
+    let wo = frame.to_local(&Vec3::from(-ray.direction));
+    (frame, wo)
+}
+
+pub fn reflect(wo: &Vec3, h: &Vec3) -> Vec3 {
+    2.0 * (wo * h) * h - wo
+}
+
+// refracts `wo` through the microfacet `h` on its side, `eta` is the ratio of the
+// index of refraction on the other side to the one on the side of `wo`
+pub fn refract(wo: &Vec3, h: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = wo * h;
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 { return None }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    Some((-1.0 / eta) * wo + (cos_i / eta - cos_t) * h)
+}
+
+// unpolarized fresnel reflectance of a dielectric, `eta` as in `refract`
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 { return 1.0 }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+// microfacet normal for `wo` and `wi` on either side of a dielectric, None if no
+// microfacet can connect them
+fn dielectric_half_vector(wo: &Vec3, wi: &Vec3, eta: f32) -> Option<Vec3> {
+    let h = if wi.z > 0.0 { wo + wi } else { wo + eta * wi };
+    if h.len_squared() <= 0.0 { return None }
+    let h = h / h.len();
+    let h = if h.z < 0.0 { -1.0 * h } else { h };
+    // both directions have to be on the correct side of the microfacet
+    let transmitted = wi.z < 0.0;
+    if wo * h <= 0.0 || (wi * h > 0.0) == transmitted { return None }
+    Some(h)
+}
+
+// bsdf times cosine and pdf of a rough dielectric that reflects with the probability of the
+// fresnel term and transmits otherwise, `wi` below the surface is a transmission
+pub fn eval_dielectric(ggx: &Ggx, wo: &Vec3, wi: &Vec3, eta: f32) -> Option<(f32, f32)> {
+    if wo.z <= 0.0 || wi.z == 0.0 { return None }
+    let h = dielectric_half_vector(wo, wi, eta)?;
+    let f = fresnel_dielectric(wo * h, eta);
+    let pdf_h = ggx.pdf_visible(wo, &h);
+    if wi.z > 0.0 {
+        let value = f * ggx.d(&h) * ggx.g(wo, wi) / (4.0 * wo.z);
+        Some((value, f * pdf_h / (4.0 * (wo * h))))
+    } else {
+        let denom = wi * h + (wo * h) / eta;
+        let denom = denom * denom;
+        let value = (1.0 - f) * ggx.d(&h) * ggx.g(wo, wi) * ((wi * h) * (wo * h)).abs() / (wo.z * denom);
+        Some((value, (1.0 - f) * pdf_h * (wi * h).abs() / denom))
+    }
+}
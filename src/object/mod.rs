@@ -1,26 +1,106 @@
 pub mod sphere;
 pub mod plane;
+pub mod mesh;
 pub mod light;
 pub mod material;
+pub mod microfacet;
+pub mod principled;
+pub mod instance;
 use crate::ray::{Ray, Intersection};
 use crate::color::Color;
+use crate::bvh::Aabb;
+use crate::mat4::Mat4;
+use crate::vec3::Vec3n;
+use crate::medium::Medium;
 
-const RAY_START_EPSILON: f32 = 1e-3;
+use std::sync::Arc;
+
+pub const RAY_START_EPSILON: f32 = 1e-3;
 
 pub trait Intersect {
     fn intersect(&self, ray: &Ray) -> Option<Intersection>;
+    // None for primitives without finite extent, these are tested for every ray
+    fn bounds(&self) -> Option<Aabb>;
+
+    // surface sampling, only needed for primitives that can be light sources
+    fn area(&self) -> f32 { 0.0 }
+    // area once `transform` is applied, None if it changes the area in a way that is not
+    // known exactly or leaves `sample_surface` no longer uniform
+    fn transformed_area(&self, transform: &Mat4) -> Option<f32> {
+        transform.uniform_scale().map(|s| s * s * self.area())
+    }
+    // uniformly distributed point on the surface as `intersect` would report it, with uv
+    // and tangents for textured emitters and `t` left at 0, at `time` for moving ones
+    fn sample_surface(&self, _time: f32) -> Option<Intersection> { None }
 }
 
-pub enum ScatteredRay {
-    Absorbed{color: Color},
-    Scattered{attenuation: Color, ray: Ray}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+    // scattering inside a medium
+    Volume,
+}
+
+// a direction sampled from a bsdf, `weight` is bsdf * cosine / pdf and
+// the pdf (per solid angle) is None for specular directions
+pub struct BsdfSample {
+    pub ray: Ray,
+    pub weight: Color,
+    pub pdf: Option<f32>,
+    pub lobe: Lobe,
 }
 
 pub trait Shade {
-    fn scatter(&self, intersection: &Intersection, ray: &Ray) -> Vec<ScatteredRay>;
+    // picks a single direction to continue the path in, None if the path is absorbed
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample>;
+
+    // bsdf times cosine of scattering `ray` into `direction`, black for specular bsdfs
+    fn eval(&self, _intersection: &Intersection, _ray: &Ray, _direction: &Vec3n) -> Color { Color::default() }
+
+    // pdf per solid angle of `sample` returning `direction`, 0 for specular bsdfs
+    fn pdf(&self, _intersection: &Intersection, _ray: &Ray, _direction: &Vec3n) -> f32 { 0.0 }
+
+    // radiance emitted from the intersection towards the origin of the ray
+    fn emitted(&self, _intersection: &Intersection, _ray: &Ray) -> Color { Color::default() }
+    fn is_emissive(&self) -> bool { false }
+
+    // surfaces that only bound a medium and let light pass unchanged
+    fn is_interface(&self) -> bool { false }
 }
 
-pub trait Object: Intersect + Shade {}
+// materials shared by several objects, like the ones of a mesh placed many times
+impl<T: Shade + ?Sized> Shade for Arc<T> {
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        (**self).sample(intersection, ray)
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        (**self).eval(intersection, ray, direction)
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        (**self).pdf(intersection, ray, direction)
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        (**self).emitted(intersection, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        (**self).is_emissive()
+    }
+
+    fn is_interface(&self) -> bool {
+        (**self).is_interface()
+    }
+}
+
+pub trait Object: Intersect + Shade {
+    // the medium filling a closed object, rays entering it travel through it
+    fn medium(&self) -> Option<&(dyn Medium + Send + Sync)> { None }
+}
 
 pub enum Solution {
     TwoRoots{t1: f32, t2: f32},
@@ -32,7 +112,7 @@ pub fn solve_quadratic(a: f32, b: f32, c: f32) -> Solution {
     let discriminant = b*b - 4.0*a*c;
     match discriminant {
         d if d < 0.0 => Solution::None,
-        d if d == 0.0 => Solution::OneRoot{t: (-b + discriminant.sqrt())/2.0*a},
+        0.0 => Solution::OneRoot{t: (-b + discriminant.sqrt())/2.0*a},
         _ => Solution::TwoRoots{t1: (-b + discriminant.sqrt())/2.0*a, t2: (-b - discriminant.sqrt())/2.0*a},
     }
 }
\ No newline at end of file
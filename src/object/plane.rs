@@ -1,22 +1,45 @@
 use crate::vec3::{Vec3, Vec3n};
-use crate::object::{Object, Intersect, Shade, ScatteredRay};
+use crate::object::{Object, Intersect, Shade, BsdfSample};
 use crate::ray::{Ray, Intersection};
+use crate::color::Color;
+use crate::bvh::Aabb;
+use crate::mat4::Mat4;
+use crate::random::sample_rng;
+
+use rand::prelude::*;
 
 struct Size {
     x: f32,
     y: f32,
 }
 
+// a rectangle centered on `pos`, its sides follow the world x axis projected onto the plane
+// (or y for planes facing along x) and the direction perpendicular to it
 pub struct Plane{
     pos: Vec3,
     normal: Vec3n,
     size: Size,
+    // unit vectors along the sides of the rectangle
+    u: Vec3n,
+    v: Vec3n,
     material: Box::<dyn Shade + Sync>,
 }
 
 impl Plane {
     pub fn new(pos: Vec3, normal: Vec3n,  size: (f32, f32), material: Box::<dyn Shade+ Sync>) -> Plane {
-        Plane{pos, normal, size: Size{x: size.0, y: size.1}, material }
+        let (u, v) = normal.tangent_frame(&Vec3{x: 1.0, y: 0.0, z: 0.0});
+        Plane{pos, normal, size: Size{x: size.0, y: size.1}, u, v, material }
+    }
+
+    // `x` and `y` along the sides from the center
+    fn intersection(&self, pos: Vec3, x: f32, y: f32, t: f32) -> Intersection {
+        let uv = (x / self.size.x + 0.5, y / self.size.y + 0.5);
+        Intersection::new(pos, self.normal, &Vec3::from(self.u), uv, t)
+    }
+
+    fn corners(&self) -> [Vec3; 4] {
+        let (du, dv) = (self.size.x / 2. * self.u, self.size.y / 2. * self.v);
+        [self.pos - du - dv, self.pos - du + dv, self.pos + du - dv, self.pos + du + dv]
     }
 }
 
@@ -24,20 +47,60 @@ impl Intersect for Plane {
     fn intersect(&self, ray: &Ray) -> Option<Intersection> {
         let t = ((self.pos - ray.origin) * self.normal) / (ray.direction * self.normal);
         let pos = ray.at(t);
-        if t > 0. && !f32::is_nan(t) &&
-            ((self.pos.x - pos.x).abs() < self.size.x/2. &&
-            (self.pos.y - pos.y).abs() < self.size.y/2. ) {
-            Some(Intersection{normal: self.normal, t, pos})
+        let (x, y) = ((pos - self.pos) * self.u, (pos - self.pos) * self.v);
+        if t > 0. && !f32::is_nan(t) && x.abs() < self.size.x/2. && y.abs() < self.size.y/2. {
+            Some(self.intersection(pos, x, y, t))
         }
         else {
             None
         }
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let bounds = self.corners().iter().fold(Aabb::empty(), |b, corner| b.grow(corner));
+        Some(Aabb::new(bounds.min - Vec3::from(1e-4), bounds.max + Vec3::from(1e-4)))
+    }
+
+    fn area(&self) -> f32 {
+        self.size.x * self.size.y
+    }
+
+    // affine transforms keep the rectangle flat, so uniform samples stay uniform
+    fn transformed_area(&self, transform: &Mat4) -> Option<f32> {
+        Some(self.area() * transform.area_scale(&self.normal))
+    }
+
+    fn sample_surface(&self, _time: f32) -> Option<Intersection> {
+        let mut rng = sample_rng();
+        let x = rng.gen_range(-0.5..0.5) * self.size.x;
+        let y = rng.gen_range(-0.5..0.5) * self.size.y;
+        Some(self.intersection(self.pos + x * self.u + y * self.v, x, y, 0.0))
+    }
 }
 
 impl Shade for Plane {
-    fn scatter(&self, intersection: &Intersection, ray: &Ray) -> Vec<ScatteredRay> {
-        self.material.scatter(intersection, ray)
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        self.material.sample(intersection, ray)
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        self.material.emitted(intersection, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.material.is_interface()
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        self.material.eval(intersection, ray, direction)
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        self.material.pdf(intersection, ray, direction)
     }
 }
 
@@ -0,0 +1,235 @@
+use crate::color::Color;
+use crate::ray::{Intersection, Ray};
+use crate::object::{Shade, BsdfSample, Lobe};
+use crate::object::material::ray_towards;
+use crate::object::microfacet::{Ggx, reflect, refract, fresnel_dielectric, eval_dielectric, local_outgoing};
+use crate::texture::Texture;
+use crate::vec3::{Vec3, Vec3n};
+use crate::random::sample_rng;
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+// index of refraction of the clearcoat layer
+const CLEARCOAT_IOR: f32 = 1.5;
+
+// a single material in the spirit of the Disney principled BSDF. All parameters are
+// textures, scalar parameters in [0, 1] use the average of the color channels.
+//
+// - `base_color` is the albedo of the diffuse base, the reflectance of metals and the
+//   tint of the transmission
+// - `metallic` blends from a dielectric to a conductor
+// - `roughness` of the specular reflection and transmission
+// - `specular` is the reflectance of dielectrics, 0.5 corresponds to an ior of 1.5
+// - `transmission` blends from an opaque dielectric to glass
+// - `clearcoat` adds a second glossy layer on top with `clearcoat_roughness`
+// - `sheen` adds a soft rim for cloth
+// - `emission` is the emitted radiance, from the side the normal points to
+pub struct Principled {
+    pub base_color: Arc<dyn Texture + Send + Sync>,
+    pub metallic: Arc<dyn Texture + Send + Sync>,
+    pub roughness: Arc<dyn Texture + Send + Sync>,
+    pub specular: Arc<dyn Texture + Send + Sync>,
+    pub transmission: Arc<dyn Texture + Send + Sync>,
+    pub clearcoat: Arc<dyn Texture + Send + Sync>,
+    pub clearcoat_roughness: Arc<dyn Texture + Send + Sync>,
+    pub sheen: Arc<dyn Texture + Send + Sync>,
+    pub emission: Option<Arc<dyn Texture + Send + Sync>>,
+}
+
+impl Principled {
+    // a rough dielectric with the given base color
+    pub fn new(base_color: Arc<dyn Texture + Send + Sync>) -> Principled {
+        Principled {
+            base_color,
+            metallic: Arc::new(Color::new(0.0)),
+            roughness: Arc::new(Color::new(0.5)),
+            specular: Arc::new(Color::new(0.5)),
+            transmission: Arc::new(Color::new(0.0)),
+            clearcoat: Arc::new(Color::new(0.0)),
+            clearcoat_roughness: Arc::new(Color::new(0.03)),
+            sheen: Arc::new(Color::new(0.0)),
+            emission: None,
+        }
+    }
+
+    // the parameters at an intersection, looked at from `wo`
+    fn lobes(&self, intersection: &Intersection, ray: &Ray, wo: &Vec3) -> Lobes {
+        let scalar = |texture: &Arc<dyn Texture + Send + Sync>| {
+            let c = texture.value(intersection);
+            ((c.r + c.g + c.b) / 3.0).clamp(0.0, 1.0)
+        };
+        let base = ray.to_spectrum(self.base_color.value(intersection));
+        let metallic = scalar(&self.metallic);
+        let transmission = scalar(&self.transmission);
+        let clearcoat = scalar(&self.clearcoat);
+        let sheen = scalar(&self.sheen);
+
+        let dielectric_f0 = 0.08 * scalar(&self.specular);
+        let ior = f32::max(1.01, (1.0 + dielectric_f0.sqrt()) / (1.0 - dielectric_f0.sqrt()));
+        let f0 = Color::new(dielectric_f0) * (1.0 - metallic) + base * metallic;
+
+        let diffuse = (1.0 - metallic) * (1.0 - transmission);
+        let glass = (1.0 - metallic) * transmission;
+
+        // lobes are picked roughly by how much they reflect towards `wo`
+        let coat_fresnel = clearcoat * fresnel_dielectric(wo.z, CLEARCOAT_IOR);
+        let estimates = [
+            diffuse * f32::max(0.05, base.luminance() + sheen),
+            (1.0 - glass) * f32::max(0.05, schlick(f0, wo.z).luminance()),
+            glass,
+            f32::max(0.05 * clearcoat, coat_fresnel),
+        ];
+        let total: f32 = estimates.iter().sum();
+
+        Lobes {
+            base,
+            roughness: scalar(&self.roughness),
+            f0,
+            eta: if intersection.from_inside(ray) { 1.0 / ior } else { ior },
+            diffuse,
+            glass,
+            clearcoat,
+            sheen,
+            specular_ggx: Ggx::new(scalar(&self.roughness), 0.0),
+            coat_ggx: Ggx::new(scalar(&self.clearcoat_roughness), 0.0),
+            probabilities: estimates.map(|e| e / total),
+        }
+    }
+}
+
+// the principled bsdf at a single intersection, in the local frame of the facing normal
+struct Lobes {
+    base: Color,
+    roughness: f32,
+    f0: Color,
+    // ratio of the index of refraction behind the surface to the one in front of it
+    eta: f32,
+    // weights of the diffuse and glass lobes, the specular lobe covers the rest
+    diffuse: f32,
+    glass: f32,
+    clearcoat: f32,
+    sheen: f32,
+    specular_ggx: Ggx,
+    coat_ggx: Ggx,
+    // of sampling the diffuse, specular, glass and clearcoat lobe
+    probabilities: [f32; 4],
+}
+
+fn schlick_weight(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn schlick(f0: Color, cos_theta: f32) -> Color {
+    f0 + (Color::new(1.0) - f0) * schlick_weight(cos_theta)
+}
+
+// pdf of sampling `wi` by reflecting `wo` on a visible microfacet
+fn reflection_pdf(ggx: &Ggx, wo: &Vec3, h: &Vec3) -> f32 {
+    ggx.pdf_visible(wo, h) / (4.0 * (wo * h))
+}
+
+impl Lobes {
+    // bsdf times cosine and pdf of scattering `wo` into `wi`
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> (Color, f32) {
+        if wo.z <= 0.0 || wi.z == 0.0 { return (Color::default(), 0.0) }
+        let [p_diffuse, p_specular, p_glass, p_coat] = self.probabilities;
+        // the clearcoat reflects part of the light before it reaches the layers below
+        let coat_transmission = |cos_theta: f32| 1.0 - self.clearcoat * fresnel_dielectric(cos_theta, CLEARCOAT_IOR);
+
+        let (glass_value, glass_pdf) = match eval_dielectric(&self.specular_ggx, wo, wi, self.eta) {
+            Some((value, pdf)) => (self.glass * value, p_glass * pdf),
+            None => (0.0, 0.0)
+        };
+        if wi.z < 0.0 {
+            return (self.base * glass_value * coat_transmission(wo.z), glass_pdf)
+        }
+
+        let h = Vec3::from((wo + wi).norm());
+        let cos_d = wi * h;
+
+        // burley diffuse with a retro reflection at grazing angles
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+        let diffuse = (self.base * (retro / PI) + Color::new(self.sheen * schlick_weight(cos_d))) * (self.diffuse * wi.z);
+
+        let ggx = &self.specular_ggx;
+        let specular = schlick(self.f0, wo * h) * ((1.0 - self.glass) * ggx.d(&h) * ggx.g(wo, wi) / (4.0 * wo.z));
+
+        let coat = &self.coat_ggx;
+        let clearcoat = self.clearcoat * fresnel_dielectric(wo * h, CLEARCOAT_IOR) * coat.d(&h) * coat.g(wo, wi) / (4.0 * wo.z);
+
+        let value = (diffuse + specular + Color::new(glass_value)) * (coat_transmission(wo.z) * coat_transmission(wi.z)) + Color::new(clearcoat);
+        let pdf = p_diffuse * wi.z / PI
+            + p_specular * reflection_pdf(ggx, wo, &h)
+            + glass_pdf
+            + p_coat * reflection_pdf(coat, wo, &h);
+        (value, pdf)
+    }
+
+    fn sample_direction(&self, wo: &Vec3, rng: &mut impl Rng) -> Option<(Vec3, Lobe)> {
+        let [p_diffuse, p_specular, p_glass, _] = self.probabilities;
+        let u = rng.gen_range(0.0..1.0);
+        let (u1, u2) = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        if u < p_diffuse {
+            Some((Vec3::from(Vec3n::from(Vec3n::new(0.0, 0.0, 1.0) + Vec3n::random_sphere())), Lobe::Diffuse))
+        } else if u < p_diffuse + p_specular {
+            Some((reflect(wo, &self.specular_ggx.sample_visible(wo, u1, u2)), Lobe::Specular))
+        } else if u < p_diffuse + p_specular + p_glass {
+            let h = self.specular_ggx.sample_visible(wo, u1, u2);
+            if rng.gen_range(0.0..1.0) < fresnel_dielectric(wo * h, self.eta) {
+                Some((reflect(wo, &h), Lobe::Specular))
+            } else {
+                refract(wo, &h, self.eta).map(|wi| (wi, Lobe::Transmission))
+            }
+        } else {
+            Some((reflect(wo, &self.coat_ggx.sample_visible(wo, u1, u2)), Lobe::Specular))
+        }
+    }
+}
+
+impl Shade for Principled {
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        let (frame, wo) = local_outgoing(intersection, ray);
+        if wo.z <= 0.0 { return None }
+        let lobes = self.lobes(intersection, ray, &wo);
+        let (wi, lobe) = lobes.sample_direction(&wo, &mut sample_rng())?;
+        // the lobes only tell reflection and transmission apart by the side of wi
+        if (wi.z < 0.0) != (lobe == Lobe::Transmission) { return None }
+        let (value, pdf) = lobes.eval(&wo, &wi);
+        if pdf <= 0.0 { return None }
+
+        let direction = frame.to_world(&wi).norm();
+        Some(BsdfSample{
+            weight: value / pdf,
+            ray: ray_towards(intersection, ray, direction),
+            pdf: Some(pdf),
+            lobe,
+        })
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        self.lobes(intersection, ray, &wo).eval(&wo, &wi).0
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        let (frame, wo) = local_outgoing(intersection, ray);
+        let wi = frame.to_local(&Vec3::from(direction));
+        self.lobes(intersection, ray, &wo).eval(&wo, &wi).1
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        match &self.emission {
+            Some(emission) if intersection.normal * ray.direction < 0.0 => ray.to_spectrum(emission.value(intersection)),
+            _ => Color::default()
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emission.is_some()
+    }
+}
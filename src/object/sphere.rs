@@ -1,16 +1,35 @@
 use crate::vec3::{Vec3, Vec3n};
-use crate::object::{Object, Intersect, Shade, Solution, ScatteredRay, solve_quadratic};
+use crate::object::{Object, Intersect, Shade, Solution, BsdfSample, solve_quadratic};
 use crate::ray::{Ray, Intersection};
+use crate::color::Color;
+use crate::bvh::Aabb;
+use crate::medium::Medium;
+
+use std::sync::Arc;
 
 pub struct Sphere{
     pos: Vec3,
     r: f32,
     material: Box::<dyn Shade + Sync>,
+    medium: Option<Arc<dyn Medium + Send + Sync>>,
 }
 
 impl Sphere {
     pub fn new(pos: Vec3, r: f32, material: Box::<dyn Shade + Sync>) -> Sphere {
-        Sphere{pos, r, material}
+        Sphere{pos, r, material, medium: None}
+    }
+
+    pub fn with_medium(self, medium: Arc<dyn Medium + Send + Sync>) -> Sphere {
+        Sphere{medium: Some(medium), ..self}
+    }
+
+    // uv are longitude and latitude with v = 1 at the top (+z), the tangent points east
+    fn intersection(pos: Vec3, normal: Vec3n, t: f32) -> Intersection {
+        let n = Vec3::from(normal);
+        let u = (f32::atan2(n.y, n.x) / (2.0 * std::f32::consts::PI)).rem_euclid(1.0);
+        let v = 1.0 - f32::acos(n.z.clamp(-1.0, 1.0)) / std::f32::consts::PI;
+        let east = Vec3{x: -n.y, y: n.x, z: 0.0};
+        Intersection::new(pos, normal, &east, (u, v), t)
     }
 }
 
@@ -22,23 +41,65 @@ impl Intersect for Sphere {
         let solution = solve_quadratic(a, b, c);
         match solution {
             Solution::None => None,
-            Solution::OneRoot{..} => None,
+            Solution::OneRoot{t} => {
+                if t <= 0. {return None}
+                let pos = ray.at(t);
+                let normal = Vec3n::from((pos - self.pos)/self.r);
+                Some(Sphere::intersection(pos, normal, t))
+            },
             Solution::TwoRoots{t1, t2} => {
                 assert!(t1 >= t2);
                 if t1 <= 0. {return None}
                 let t = if t2 > 0. { t2 } else { t1 };
                 let normal = Vec3n::from((ray.at(t) - self.pos)/self.r);
                 let pos = ray.at(t);
-                Some(Intersection{normal, pos, t})
+                Some(Sphere::intersection(pos, normal, t))
             },
         }
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.pos - Vec3::from(self.r), self.pos + Vec3::from(self.r)))
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.r * self.r
+    }
+
+    fn sample_surface(&self, _time: f32) -> Option<Intersection> {
+        let normal = Vec3n::random_sphere();
+        Some(Sphere::intersection(self.pos + self.r * normal, normal, 0.0))
+    }
 }
 
 impl Shade for Sphere {
-    fn scatter(&self, intersection: &Intersection, ray: &Ray) -> Vec<ScatteredRay>{
-        self.material.scatter(intersection, ray)
+    fn sample(&self, intersection: &Intersection, ray: &Ray) -> Option<BsdfSample> {
+        self.material.sample(intersection, ray)
+    }
+
+    fn emitted(&self, intersection: &Intersection, ray: &Ray) -> Color {
+        self.material.emitted(intersection, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.material.is_interface()
+    }
+
+    fn eval(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> Color {
+        self.material.eval(intersection, ray, direction)
+    }
+
+    fn pdf(&self, intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> f32 {
+        self.material.pdf(intersection, ray, direction)
     }
 }
 
-impl Object for Sphere {}
\ No newline at end of file
+impl Object for Sphere {
+    fn medium(&self) -> Option<&(dyn Medium + Send + Sync)> {
+        self.medium.as_deref()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,39 @@
+use rand::prelude::*;
+
+use std::cell::RefCell;
+
+thread_local! {
+    static GENERATOR: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+// the random numbers of all sampling, one generator per thread like `rand::thread_rng`,
+// but tests can seed the one of their thread to be reproducible
+#[derive(Clone, Copy)]
+pub struct SampleRng;
+
+pub fn sample_rng() -> SampleRng {
+    SampleRng
+}
+
+#[cfg(test)]
+pub fn seed(seed: u64) {
+    GENERATOR.with(|generator| *generator.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        GENERATOR.with(|generator| generator.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        GENERATOR.with(|generator| generator.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        GENERATOR.with(|generator| generator.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        GENERATOR.with(|generator| generator.borrow_mut().try_fill_bytes(dest))
+    }
+}
@@ -1,19 +1,65 @@
 use crate::vec3::{Vec3, Vec3n};
+use crate::color::Color;
+use crate::spectrum::Wavelengths;
 
+#[derive(Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3n,
-    pub inside: bool
+    // carried by the paths of the spectral mode, None when rendering in rgb
+    pub wavelengths: Option<Wavelengths>,
+    // moment within the shutter interval, all rays of a path share it
+    pub time: f32,
 }
 
 impl Ray {
+    // an rgb ray at time 0
+    pub fn new(origin: Vec3, direction: Vec3n) -> Ray {
+        Ray{origin, direction, wavelengths: None, time: 0.0}
+    }
+
     pub fn at(&self, t: f32) -> Vec3 {
         self.origin + Vec3::from(self.direction)*t
     }
+
+    // an rgb color as the values the ray carries, the spectrum at its wavelengths in spectral mode
+    pub fn to_spectrum(&self, c: Color) -> Color {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.uplift(&c),
+            None => c
+        }
+    }
 }
 
+#[derive(Clone, Copy)]
 pub struct Intersection {
    pub pos: Vec3,
+   // shading normal, may be interpolated or perturbed by a normal map
    pub normal: Vec3n,
+   // normal of the actual surface, on the same side as the shading normal
+   pub geometric_normal: Vec3n,
+   // directions of increasing u and v, perpendicular to the shading normal
+   pub tangent: Vec3n,
+   pub bitangent: Vec3n,
+   // texture coordinates
+   pub uv: (f32, f32),
    pub t: f32
-}
\ No newline at end of file
+}
+
+impl Intersection {
+    // the tangent follows `dpdu` projected onto the surface
+    pub fn new(pos: Vec3, normal: Vec3n, dpdu: &Vec3, uv: (f32, f32), t: f32) -> Intersection {
+        let (tangent, bitangent) = normal.tangent_frame(dpdu);
+        Intersection{pos, normal, geometric_normal: normal, tangent, bitangent, uv, t}
+    }
+
+    // whether the ray arrives from behind the actual surface, from inside a closed object
+    pub fn from_inside(&self, ray: &Ray) -> bool {
+        self.geometric_normal * ray.direction > 0.0
+    }
+
+    // the shading normal flipped to the side of the surface the ray arrives from
+    pub fn facing_normal(&self, ray: &Ray) -> Vec3n {
+        if self.from_inside(ray) { -self.normal } else { self.normal }
+    }
+}
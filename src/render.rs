@@ -1,39 +1,181 @@
-use crate::color::Color;
-use crate::image::{Image, Resolution};
+use crate::image::Image;
+use crate::tonemap::Tonemap;
+use crate::error::Error;
 
 use std::path::Path;
-use std::path::PathBuf;
-use std::io::Write;
+use std::io::{self, Write, BufWriter};
 use std::fs::File;
 
 impl Image {
-    fn tonemap(&self) -> Vec<u8> {
-        let mut data = vec![0; self.resolution.width * self.resolution.height * 3];
-        for (i,c) in self.get_data().iter().enumerate() {
-            let offset = i*3;
-
-            // filmic tonemap formula by Jim Hejl and Richard Burgess-Dawson
-            let x = Color{
-                r: f32::max(0.0,c.r-0.004),
-                g: f32::max(0.0,c.g-0.004),
-                b: f32::max(0.0,c.b-0.004),
-            };
-            let color = (x*(6.2*x+0.5))/(x*(6.2*x+1.7)+0.06);
+    // the format is chosen by the file extension, .hdr, .pfm and .exr keep the linear radiance
+    // and ignore the tonemap
+    pub fn save(&self, filename: &Path, tonemap: &Tonemap) -> Result<(), Error> {
+        let extension = filename.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        let result = match extension.as_str() {
+            "ppm" => self.save_ppm(filename, tonemap),
+            "png" => self.save_png(filename, tonemap),
+            "hdr" => self.save_hdr(filename),
+            "pfm" => self.save_pfm(filename),
+            "exr" => self.save_exr(filename),
+            _ => return Err(Error::UnsupportedFormat{path: filename.to_path_buf()})
+        };
+        result.map_err(|e| Error::io(filename, e))
+    }
+
+    fn save_ppm(&self, filename: &Path, tonemap: &Tonemap) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(filename)?);
+        let header = format!("P6 {} {} 255\n", self.resolution.width, self.resolution.height);
+        file.write_all(header.as_bytes())?;
+        let data = tonemap.apply(self);
+        file.write_all(&data)?;
+        Ok(())
+    }
+
+    fn save_png(&self, filename: &Path, tonemap: &Tonemap) -> io::Result<()> {
+        let file = BufWriter::new(File::create(filename)?);
+        let mut encoder = png::Encoder::new(file, self.resolution.width as u32, self.resolution.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&tonemap.apply(self)).map_err(io::Error::other)?;
+        Ok(())
+    }
 
-            data[offset] = (color.r*255.0) as u8;
-            data[offset+1] = (color.g*255.0) as u8;
-            data[offset+2] = (color.b*255.0) as u8;
+    // Radiance RGBE with uncompressed scanlines
+    fn save_hdr(&self, filename: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(filename)?);
+        let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", self.resolution.height, self.resolution.width);
+        file.write_all(header.as_bytes())?;
+        for c in self.get_data().iter() {
+            // infinite fireflies become the largest value rgbe can hold, NaN is black
+            let finite = |x: f32| if x.is_nan() { 0.0 } else { x.min(f32::MAX) };
+            let (r, g, b) = (finite(c.r), finite(c.g), finite(c.b));
+            let v = r.max(g).max(b);
+            let rgbe = if v < 1e-32 {
+                [0, 0, 0, 0]
+            } else {
+                // v = m * 2^e with m in [0.5, 1), e limited to what the exponent byte holds
+                let e = (v.log2().floor() as i32 + 1).clamp(-128, 127);
+                let scale = 256.0 / 2f32.powi(e);
+                let byte = |x: f32| (x.max(0.0) * scale).min(255.0) as u8;
+                [byte(r), byte(g), byte(b), (e + 128) as u8]
+            };
+            file.write_all(&rgbe)?;
         }
-        data
+        Ok(())
     }
 
-    pub fn save(&self, filename: &PathBuf) -> std::io::Result<()> {
-        let path = Path::new(filename);
-        let mut file = File::create(&path)?;
-        let header = format!("P6 {} {} 255\n", self.resolution.width, self.resolution.height);
-        file.write(header.as_bytes())?;
-        let data = self.tonemap();
-        file.write(&data)?;
+    // portable float map, little endian and stored bottom row first
+    fn save_pfm(&self, filename: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(filename)?);
+        let header = format!("PF\n{} {}\n-1.0\n", self.resolution.width, self.resolution.height);
+        file.write_all(header.as_bytes())?;
+        for row in self.get_data().chunks(self.resolution.width).rev() {
+            for c in row {
+                file.write_all(&c.r.to_le_bytes())?;
+                file.write_all(&c.g.to_le_bytes())?;
+                file.write_all(&c.b.to_le_bytes())?;
+            }
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn save_exr(&self, filename: &Path) -> io::Result<()> {
+        let data = self.get_data();
+        let width = self.resolution.width;
+        exr::prelude::write_rgb_file(filename, width, self.resolution.height, |x, y| {
+            let c = data[y * width + x];
+            (c.r, c.g, c.b)
+        }).map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::image::Resolution;
+
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rayt-{}-{}", std::process::id(), name))
+    }
+
+    // a 2x3 image with a different color in every pixel, brighter towards the bottom
+    fn gradient() -> Image {
+        let mut image = Image::new(Resolution::new(2, 3));
+        for (i, c) in image.get_data_mut().iter_mut().enumerate() {
+            *c = Color{r: 0.1 * i as f32, g: 2.0 + i as f32, b: 300.0 * i as f32};
+        }
+        image
+    }
+
+    #[test]
+    fn hdr_round_trip() {
+        let mut image = gradient();
+        image.get_data_mut()[5] = Color{r: f32::INFINITY, g: f32::NAN, b: 1.0};
+        let path = temp_file("round_trip.hdr");
+        image.save(&path, &Tonemap::default()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let loaded = Image::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 3 +X 2\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let pixels = &bytes[header.len()..];
+        assert_eq!(pixels.len(), 6 * 4);
+        // 2 = 128 * 2^(130 - 128 - 8), the other channels share its exponent
+        assert_eq!(&pixels[..4], &[0, 128, 0, 130]);
+        // the infinite firefly keeps the largest value, NaN is black
+        assert_eq!(&pixels[20..], &[255, 0, 0, 255]);
+
+        assert_eq!(loaded.resolution, image.resolution);
+        for (a, b) in image.get_data().iter().zip(loaded.get_data()).take(5) {
+            // rgbe keeps 8 bits of mantissa relative to the brightest channel
+            let tolerance = a.r.max(a.g).max(a.b) / 128.0;
+            for (a, b) in [(a.r, b.r), (a.g, b.g), (a.b, b.b)] {
+                assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+            }
+        }
+        assert!(loaded.get_data()[5].r > 1e38);
+    }
+
+    #[test]
+    fn pfm_layout() {
+        let image = gradient();
+        let path = temp_file("layout.pfm");
+        image.save(&path, &Tonemap::default()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // a negative scale means little endian
+        let header = b"PF\n2 3\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let floats: Vec<f32> = bytes[header.len()..].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        assert_eq!(floats.len(), 6 * 3);
+        // the bottom row comes first
+        let data = image.get_data();
+        for (i, pixel) in floats.chunks(3).enumerate() {
+            let (row, column) = (2 - i / 2, i % 2);
+            let c = data[row * 2 + column];
+            assert_eq!(pixel, &[c.r, c.g, c.b], "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let image = gradient();
+        let path = temp_file("round_trip.exr");
+        image.save(&path, &Tonemap::default()).unwrap();
+        let loaded = exr::prelude::read_first_rgba_layer_from_file(&path,
+            |resolution, _| vec![Color::default(); resolution.width() * resolution.height()],
+            |pixels: &mut Vec<Color>, position, (r, g, b, _): (f32, f32, f32, f32)| pixels[position.y() * 2 + position.x()] = Color{r, g, b},
+        ).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let pixels = loaded.layer_data.channel_data.pixels;
+        for (a, b) in image.get_data().iter().zip(&pixels) {
+            assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+        }
+    }
+}
@@ -1,98 +1,237 @@
-use crate::camera::Camera;
+use crate::camera::{Camera, Progressive, Projection};
 use crate::image::{Image, Resolution};
-use crate::vec3::{Vec3, Vec3n};
-use crate::object::{Object, ScatteredRay};
-use crate::object::light::{AmbientLight, Emissive};
-use crate::object::material::{DiffuseSpecular, Metalic, Refractive};
-use crate::object::{sphere::Sphere, plane::Plane};
+use crate::object::{Object, Lobe, RAY_START_EPSILON};
+use crate::object::light::AmbientLight;
 use crate::ray::{Ray, Intersection};
 use crate::color::Color;
+use crate::vec3::{Vec3, Vec3n};
+use crate::bvh::{Aabb, Bvh};
+use crate::spectrum::Wavelengths;
+use crate::medium::{Medium, MediumEvent, HenyeyGreenstein};
+use crate::random::sample_rng;
+
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+
+// path length limits, paths longer than `min_depth` are terminated by russian roulette
+#[derive(Debug, Clone, Copy)]
+pub struct PathSettings {
+    pub min_depth: u32,
+    pub max_depth: u32,
+    pub max_diffuse_depth: u32,
+    pub max_specular_depth: u32,
+    pub max_transmission_depth: u32,
+    pub max_volume_depth: u32,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings{min_depth: 3, max_depth: 64, max_diffuse_depth: 8, max_specular_depth: 32, max_transmission_depth: 32, max_volume_depth: 32}
+    }
+}
+
+// state of a path at a vertex
+#[derive(Debug, Clone, Copy)]
+struct PathState {
+    depth: u32,
+    diffuse: u32,
+    specular: u32,
+    transmission: u32,
+    volume: u32,
+    throughput: Color,
+    // pdf with which the last bounce was sampled, None for camera rays and specular bounces
+    bsdf_pdf: Option<f32>,
+    // whether next event estimation at the last vertex was weighted against bsdf sampling
+    light_mis: bool,
+}
+
+impl PathState {
+    fn new() -> PathState {
+        PathState{depth: 0, diffuse: 0, specular: 0, transmission: 0, volume: 0, throughput: Color::new(1.0), bsdf_pdf: None, light_mis: false}
+    }
+
+    // None if the path is not allowed to continue with this lobe
+    fn bounce(&self, settings: &PathSettings, lobe: Lobe, attenuation: &Color, pdf: Option<f32>) -> Option<PathState> {
+        let mut next = PathState{depth: self.depth + 1, throughput: self.throughput * attenuation, bsdf_pdf: pdf, ..*self};
+        let (count, max) = match lobe {
+            Lobe::Diffuse => { next.diffuse += 1; (next.diffuse, settings.max_diffuse_depth) },
+            Lobe::Specular => { next.specular += 1; (next.specular, settings.max_specular_depth) },
+            Lobe::Transmission => { next.transmission += 1; (next.transmission, settings.max_transmission_depth) },
+            Lobe::Volume => { next.volume += 1; (next.volume, settings.max_volume_depth) },
+        };
+        if count > max || next.depth > settings.max_depth { None } else { Some(next) }
+    }
+
+    // russian roulette, survivors are weighted up to stay unbiased
+    fn roulette(mut self, settings: &PathSettings) -> Option<PathState> {
+        if self.depth > settings.min_depth {
+            let survival = f32::min(1.0, self.throughput.r.max(self.throughput.g).max(self.throughput.b));
+            if sample_rng().gen_range(0.0..1.0) >= survival { return None }
+            self.throughput = self.throughput / survival;
+        }
+        Some(self)
+    }
+}
+
+// a point where the path scatters, on a surface or inside a medium
+enum Vertex<'a> {
+    Surface{object: &'a dyn Object, intersection: &'a Intersection},
+    Medium{phase: &'a HenyeyGreenstein, pos: Vec3},
+}
+
+// assembles a scene in code, objects are added in order and the bvh is built by `build`
+pub struct SceneBuilder {
+    camera: Camera,
+    objs: Vec<Box<dyn Object + Sync>>,
+    ambient_light: AmbientLight,
+    path_settings: PathSettings,
+    use_bvh: bool,
+    spectral: bool,
+    fog: Option<Arc<dyn Medium + Send + Sync>>,
+}
+
+impl SceneBuilder {
+    pub fn new(camera: Camera) -> SceneBuilder {
+        SceneBuilder{camera, objs: Vec::new(), ambient_light: AmbientLight::new(), path_settings: PathSettings::default(), use_bvh: true, spectral: false, fog: None}
+    }
 
-use std::path::PathBuf;
+    pub fn object(mut self, object: impl Object + Sync + 'static) -> SceneBuilder {
+        self.objs.push(Box::new(object));
+        self
+    }
+
+    pub fn objects(mut self, objs: impl IntoIterator<Item = Box<dyn Object + Sync>>) -> SceneBuilder {
+        self.objs.extend(objs);
+        self
+    }
 
-const MAX_RECURSION_DEPTH : u8 = 8;
+    pub fn ambient_light(mut self, ambient_light: AmbientLight) -> SceneBuilder {
+        self.ambient_light = ambient_light;
+        self
+    }
 
+    pub fn path_settings(mut self, path_settings: PathSettings) -> SceneBuilder {
+        self.path_settings = path_settings;
+        self
+    }
+
+    pub fn disable_bvh(mut self) -> SceneBuilder {
+        self.use_bvh = false;
+        self
+    }
+
+    pub fn spectral(mut self) -> SceneBuilder {
+        self.spectral = true;
+        self
+    }
+
+    pub fn fog(mut self, fog: Arc<dyn Medium + Send + Sync>) -> SceneBuilder {
+        self.fog = Some(fog);
+        self
+    }
+
+    pub fn build(self) -> Scene {
+        let mut scene = Scene::new(self.camera, self.objs, self.ambient_light);
+        scene.path_settings = self.path_settings;
+        scene.use_bvh = self.use_bvh;
+        scene.spectral = self.spectral;
+        scene.fog = self.fog;
+        scene
+    }
+}
 
 pub struct Scene {
     camera: Camera,
     objs: Vec<Box<dyn Object + Sync>>,
+    bvh: Bvh,
+    // of all bounded objects, the fog ends there
+    bounds: Aabb,
+    unbounded: Vec<usize>,
+    use_bvh: bool,
+    // indices of the emissive objects
+    lights: Vec<usize>,
     ambient_light: AmbientLight,
-    rays_per_pixel: u32
+    path_settings: PathSettings,
+    // paths carry wavelengths instead of rgb
+    spectral: bool,
+    // medium filling the space between the objects
+    fog: Option<Arc<dyn Medium + Send + Sync>>,
 }
 
 impl Scene {
-    pub fn new(rays_per_pixel: u32, hdr_path: &PathBuf) -> Scene {
-        let camera = Camera::new(
-            90.0, 
-            &Vec3{x: 0.0, y: -10.0, z: 3.0},
-            &Vec3{x: 0.0, y: -1.5, z: 3.0},
-            &Vec3n::new(0.0, 0.0, 1.0),
-            0.
-        );
-
-        let color_orange = Color::from_hex("#F29F80").unwrap();
-        let color_red = Color::from_hex("#D95578").unwrap();
-        let color_white = Color::from_hex("#FFFFFF").unwrap();
-
-
-        let mut objs: Vec<Box<dyn Object + Sync>> = Vec::new();
-
-        let color = color_red * color_orange;
-        let material = DiffuseSpecular{diffuse: color, ambient: color * 0.2, specular: color, shineness: 128.0};
-        let material = Refractive::new(&Color::new(1.1));
-        let sphere = Sphere::new(Vec3{x: 0.0, y: -3.0, z: 5.0}, 3.0, Box::new(material));
-        objs.push(Box::new(sphere));
-        let color = color_red * 2.0;
-        let material = DiffuseSpecular{diffuse: color, ambient: color * 0.2, specular: color, shineness: 128.0};
-        //let material = Metalic::silver();
-        let sphere = Sphere::new(Vec3{x: 4.0, y: 2.0, z: 3.0}, 3.0, Box::new(material));
-        objs.push(Box::new(sphere));
-        let color = color_red * color_red;
-        let material = DiffuseSpecular{diffuse: color, ambient: color, specular: color, shineness: 128.0};
-        let material = Metalic::gold();
-        let sphere = Sphere::new(Vec3{x: -4.0, y: 2.0, z: 3.0}, 3.0, Box::new(material));
-        objs.push(Box::new(sphere));
-
-        let color = color_white;
-        let box_size = 30.0;
-        let material = DiffuseSpecular{diffuse: color, ambient: color, specular: color, shineness: 2.0};
-        let plane = Plane::new(Vec3{x: 0.0, y: 0.0, z: 0.0}, Vec3n::from(Vec3{x: 0.0, y: 0.0, z: 1.0}), (box_size, box_size), Box::new(material));
-        objs.push(Box::new(plane));
-
-        // let material = DiffuseSpecular{diffuse: color, ambient: color, specular: color, shineness: 2.0};
-        // let plane = Plane::new(Vec3{x: 0.0, y: box_size/2., z: 0.0}, Vec3n::from(Vec3{x: 0.0, y: -1.0, z: 0.0}), (box_size, box_size), Box::new(material));
-        // objs.push(Box::new(plane));
-        // let color = color_red;
-        // let material = DiffuseSpecular{diffuse: color, ambient: color, specular: color, shineness: 2.0};
-        // let plane = Plane::new(Vec3{x: box_size/2., y: 0.0, z: 0.0}, Vec3n::from(Vec3{x: -1.0, y: 0.0, z: 0.0}), (box_size, box_size), Box::new(material));
-        // objs.push(Box::new(plane));
-        // let color = color_orange;
-        // let material = DiffuseSpecular{diffuse: color, ambient: color, specular: color, shineness: 2.0};
-        // let plane = Plane::new(Vec3{x: -box_size/2., y: 0.0, z: 0.0}, Vec3n::from(Vec3{x: 1.0, y: 0.0, z: 0.0}), (box_size, box_size), Box::new(material));
-        // objs.push(Box::new(plane));
-
-        let material = Emissive{color: Color::new(400.0)};
-        let plane = Plane::new(Vec3{x: -15.0, y: -5.0, z: 10.0}, Vec3n::from(Vec3{x: -1.0, y: -1.0, z: -1.0}), (10., 10.), Box::new(material));
-        objs.push(Box::new(plane));
-        let material = Emissive{color: Color::new(400.0)};
-        let plane = Plane::new(Vec3{x: 15.0, y: -5.0, z: 10.0}, Vec3n::from(Vec3{x: 1.0, y: -1.0, z: -1.0}), (10., 10.), Box::new(material));
-        objs.push(Box::new(plane));
-
-        let ambient_light = AmbientLight::load(hdr_path).unwrap_or(AmbientLight::new());
-        Scene{camera, objs, ambient_light, rays_per_pixel}
-    }
-
-    pub fn render(&self, resolution: Resolution) -> Image {
-        self.camera.take_picture(resolution, &self, self.rays_per_pixel)
-    }
-
-    pub fn first_intersect(&self, ray: &Ray) -> Option<(&Box<dyn Object + Sync>, Intersection)> {
-        self.objs.iter().map(|o|{
-            match o.intersect(ray) {
-                Some(intersection) => Some((o, intersection)),
-                None => None
-            }
-        }).reduce(|a, b| {
+    pub fn new(camera: Camera, objs: Vec<Box<dyn Object + Sync>>, ambient_light: AmbientLight) -> Scene {
+        let bounds: Vec<_> = objs.iter().enumerate().filter_map(|(i, o)| o.bounds().map(|b| (i, b))).collect();
+        let unbounded = objs.iter().enumerate().filter(|(_, o)| o.bounds().is_none()).map(|(i, _)| i).collect();
+        let bvh = Bvh::new(&bounds);
+        let scene_bounds = bounds.iter().fold(Aabb::empty(), |b, (_, o)| b.union(o));
+        let lights = objs.iter().enumerate().filter(|(_, o)| o.is_emissive() && o.area() > 0.0).map(|(i, _)| i).collect();
+        Scene{camera, objs, bvh, bounds: scene_bounds, unbounded, use_bvh: true, lights, ambient_light, path_settings: PathSettings::default(), spectral: false, fog: None}
+    }
+
+    pub fn builder(camera: Camera) -> SceneBuilder {
+        SceneBuilder::new(camera)
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    // overrides the projection of the camera
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.camera.set_projection(projection);
+    }
+
+    pub fn set_ambient_light(&mut self, ambient_light: AmbientLight) {
+        self.ambient_light = ambient_light;
+    }
+
+    pub fn set_path_settings(&mut self, path_settings: PathSettings) {
+        self.path_settings = path_settings;
+    }
+
+    // falls back to testing every object for every ray
+    pub fn disable_bvh(&mut self) {
+        self.use_bvh = false;
+    }
+
+    // traces the paths at hero wavelengths and converts them to rgb with the color matching
+    // functions, needed for dispersion and measured metals
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral;
+    }
+
+    // global fog or haze, it fills the bounding box of the scene so the environment
+    // still shines through it
+    pub fn set_fog(&mut self, fog: Option<Arc<dyn Medium + Send + Sync>>) {
+        self.fog = fog;
+    }
+
+    pub fn render(&self, resolution: Resolution, rays_per_pixel: u32) -> Image {
+        self.camera.take_picture(resolution, self, rays_per_pixel)
+    }
+
+    pub fn render_progressive(&self, resolution: Resolution, settings: &Progressive) -> Image {
+        self.camera.take_picture_progressive(resolution, self, settings)
+    }
+
+    pub fn first_intersect(&self, ray: &Ray) -> Option<(&dyn Object, Intersection)> {
+        if !self.use_bvh {
+            return Scene::closest(self.objs.iter().map(|o| {
+                o.intersect(ray).map(|intersection| (o.as_ref() as &dyn Object, intersection))
+            }))
+        }
+
+        let bounded = self.bvh.traverse(ray, |i| self.objs[i].intersect(ray))
+            .map(|(i, intersection)| (self.objs[i].as_ref() as &dyn Object, intersection));
+        Scene::closest(std::iter::once(bounded).chain(self.unbounded.iter().map(|&i| {
+            self.objs[i].intersect(ray).map(|intersection| (self.objs[i].as_ref() as &dyn Object, intersection))
+        })))
+    }
+
+    fn closest<'a>(intersections: impl Iterator<Item = Option<(&'a dyn Object, Intersection)>>) -> Option<(&'a dyn Object, Intersection)> {
+        intersections.reduce(|a, b| {
             match (&a, &b) {
                 (Some((_, Intersection{t: ta,..})), Some((_, Intersection{t: tb,..}))) => {
                     if ta < tb { a } else { b }
@@ -104,20 +243,591 @@ impl Scene {
         }).unwrap_or(None)
     }
 
-    pub fn trace(&self, ray: &Ray, depth: u8) -> Color {
-        if depth > MAX_RECURSION_DEPTH { return self.ambient_light.get_color(ray) }
+    // radiance arriving along the ray in rgb
+    pub fn trace(&self, ray: &Ray) -> Color {
+        if !self.spectral {
+            return self.trace_path(ray)
+        }
+        let wavelengths = Wavelengths::sample(sample_rng().gen_range(0.0..1.0));
+        let values = self.trace_path(&Ray{wavelengths: Some(wavelengths), ..ray.clone()});
+        wavelengths.to_rgb(&values)
+    }
+
+    // follows a single path, sampling one bsdf lobe per bounce and tracking the distance
+    // to the next scattering event in media
+    fn trace_path(&self, ray: &Ray) -> Color {
+        let mut rng = sample_rng();
+        let mut ray = ray.clone();
+        let mut path = PathState::new();
+        let mut radiance = Color::default();
+        // camera rays start in the fog
+        let mut medium = self.fog();
+        // where the path last scattered, interfaces move the origin of the ray but not this
+        let mut vertex = ray.origin;
+
+        loop {
+            let hit = self.first_intersect(&ray);
+
+            let t_hit = hit.as_ref().map_or(f32::INFINITY, |(_, intersection)| intersection.t);
+            if let Some((current, (t_min, t_max))) = medium.zip(self.medium_span(&ray, t_hit)) {
+                let inside = Ray{origin: ray.at(t_min), ..ray.clone()};
+                match current.sample(&inside, t_max - t_min, &path.throughput) {
+                    MediumEvent::Passed{weight} => path.throughput = path.throughput * weight,
+                    MediumEvent::Absorbed => return radiance,
+                    MediumEvent::Scattered{t, weight} => {
+                        path.throughput = path.throughput * weight;
+                        let (phase, pos) = (current.phase(), inside.at(t));
+                        let use_mis = path.bounce(&self.path_settings, Lobe::Volume, &Color::new(1.0), None).is_some();
+                        let direct = self.sample_light(&Vertex::Medium{phase, pos}, &ray, medium, use_mis);
+                        radiance = radiance + path.throughput * direct;
 
-        if let Some((object, intersection)) = self.first_intersect(ray) {
-            object.scatter(&intersection, ray).iter().map(|scattered_ray| {
-                match scattered_ray {
-                    ScatteredRay::Scattered{attenuation, ray: next_ray} =>
-                        attenuation * self.trace(&next_ray, depth + 1),
-                    ScatteredRay::Absorbed{color} => *color
+                        // the phase function is sampled exactly, which leaves the throughput as is
+                        let direction = phase.sample(&ray.direction, rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+                        let pdf = phase.eval(ray.direction * direction);
+                        let Some(mut next) = path.bounce(&self.path_settings, Lobe::Volume, &Color::new(1.0), Some(pdf)) else { return radiance };
+                        next.light_mis = use_mis;
+                        let Some(next) = next.roulette(&self.path_settings) else { return radiance };
+                        path = next;
+                        ray = Ray{origin: pos, direction, ..ray};
+                        vertex = pos;
+                        continue
+                    }
                 }
-                
-            }).reduce(|a, b| { a + b }).unwrap_or_default()
+            }
+
+            let Some((object, intersection)) = hit else {
+                // a sampled environment was already counted in full by next event estimation
+                // at the last vertex unless that was weighted against this bsdf sample
+                let weight = match path.bsdf_pdf {
+                    Some(pdf) if path.light_mis => power_heuristic(pdf, self.environment_pdf(&ray.direction)),
+                    Some(_) if self.ambient_light.is_sampled() => 0.0,
+                    _ => 1.0
+                };
+                return radiance + path.throughput * self.ambient_light.get_color(&ray) * weight
+            };
+
+            if object.is_interface() {
+                medium = self.medium_behind(object, &intersection, &ray.direction);
+                ray.origin = intersection.pos + RAY_START_EPSILON * ray.direction;
+                continue
+            }
+
+            // lights without an area are never sampled directly, bsdf samples are all there is
+            let emitted = match path.bsdf_pdf {
+                Some(pdf) if object.is_emissive() && object.area() > 0.0 => {
+                    // this light could also have been found by sampling it directly, which counted
+                    // it in full if it was not weighted against this bsdf sample
+                    if path.light_mis {
+                        let light_pdf = self.light_pdf(object, &intersection, &vertex, &ray.direction);
+                        object.emitted(&intersection, &ray) * power_heuristic(pdf, light_pdf)
+                    } else {
+                        Color::default()
+                    }
+                },
+                _ => object.emitted(&intersection, &ray)
+            };
+            // if some lobe can not bounce any more, the light can only be found by sampling it.
+            // rough specular and transmission lobes have a pdf too, so all of them have to be able
+            // to continue before next event estimation may leave a share to the bsdf sample
+            let use_mis = [Lobe::Diffuse, Lobe::Specular, Lobe::Transmission].into_iter()
+                .all(|lobe| path.bounce(&self.path_settings, lobe, &Color::new(1.0), None).is_some());
+            let direct = self.sample_light(&Vertex::Surface{object, intersection: &intersection}, &ray, medium, use_mis);
+            radiance = radiance + path.throughput * (emitted + direct);
+
+            let Some(sample) = object.sample(&intersection, &ray) else { return radiance };
+            if !Scene::is_consistent(&intersection, &ray, &sample.ray.direction) { return radiance }
+            let Some(mut next) = path.bounce(&self.path_settings, sample.lobe, &sample.weight, sample.pdf) else { return radiance };
+            next.light_mis = use_mis;
+            let Some(next) = next.roulette(&self.path_settings) else { return radiance };
+            if Scene::crosses(&intersection, &ray, &sample.ray.direction) {
+                medium = self.medium_behind(object, &intersection, &sample.ray.direction);
+            }
+            path = next;
+            ray = sample.ray;
+            vertex = ray.origin;
+        }
+    }
+
+    fn fog(&self) -> Option<&(dyn Medium + Send + Sync)> {
+        self.fog.as_deref()
+    }
+
+    // the medium on the side of the surface of `object` that `direction` points to
+    fn medium_behind<'a>(&'a self, object: &'a dyn Object, intersection: &Intersection, direction: &Vec3n) -> Option<&'a (dyn Medium + Send + Sync)> {
+        if intersection.geometric_normal * direction < 0.0 { object.medium() } else { self.fog() }
+    }
+
+    // the part of the ray up to `t` inside the bounds of the scene, media and the fog do
+    // not reach further
+    fn medium_span(&self, ray: &Ray, t: f32) -> Option<(f32, f32)> {
+        let direction = Vec3::from(ray.direction);
+        self.bounds.span(&ray.origin, &Vec3{x: 1.0 / direction.x, y: 1.0 / direction.y, z: 1.0 / direction.z}, t)
+    }
+
+    // whether `direction` continues through the surface the ray arrived at
+    fn crosses(intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> bool {
+        (intersection.geometric_normal * ray.direction) * (intersection.geometric_normal * direction) > 0.0
+    }
+
+    // fraction of the light arriving along the ray from `distance`, surfaces block it
+    // unless they are interfaces and media attenuate it
+    fn transmittance<'a>(&'a self, ray: &Ray, distance: f32, mut medium: Option<&'a (dyn Medium + Send + Sync)>) -> Color {
+        let mut ray = ray.clone();
+        let mut distance = distance;
+        let mut transmittance = Color::new(1.0);
+        loop {
+            let hit = self.first_intersect(&ray).filter(|(_, hit)| hit.t < distance - 2.0 * RAY_START_EPSILON);
+            let t = hit.as_ref().map_or(distance, |(_, hit)| hit.t);
+            if let Some((medium, (t_min, t_max))) = medium.zip(self.medium_span(&ray, t)) {
+                transmittance = transmittance * medium.transmittance(&Ray{origin: ray.at(t_min), ..ray.clone()}, t_max - t_min);
+            }
+            let Some((object, hit)) = hit else { return transmittance };
+            if !object.is_interface() { return Color::default() }
+            medium = self.medium_behind(object, &hit, &ray.direction);
+            ray.origin = hit.pos + RAY_START_EPSILON * ray.direction;
+            distance -= hit.t + RAY_START_EPSILON;
+        }
+    }
+
+    // shading normals can scatter into directions on the wrong side of the actual surface,
+    // a direction has to reflect or cross the actual surface like it does the shading one.
+    // this does not depend on the lobe, so light samples towards the far side of rough
+    // glass pass as well
+    fn is_consistent(intersection: &Intersection, ray: &Ray, direction: &Vec3n) -> bool {
+        let geometric = (intersection.geometric_normal * ray.direction) * (intersection.geometric_normal * direction);
+        let shading = (intersection.normal * ray.direction) * (intersection.normal * direction);
+        geometric != 0.0 && (geometric > 0.0) == (shading > 0.0)
+    }
+
+    // pdf per solid angle of sampling `intersection` on `light` from `origin`
+    fn light_pdf(&self, light: &dyn Object, intersection: &Intersection, origin: &Vec3, direction: &Vec3n) -> f32 {
+        let cos_light = (intersection.geometric_normal * direction).abs();
+        let area = light.area();
+        if cos_light <= 0.0 || area <= 0.0 { return 0.0 }
+        let distance_squared = (intersection.pos - origin).len_squared();
+        distance_squared / (cos_light * area * self.light_count() as f32)
+    }
+
+    // pdf per solid angle of sampling `direction` on the environment
+    fn environment_pdf(&self, direction: &Vec3n) -> f32 {
+        if !self.ambient_light.is_sampled() { return 0.0 }
+        self.ambient_light.pdf(direction) / self.light_count() as f32
+    }
+
+    // the environment counts as one light if it is importance sampled
+    fn light_count(&self) -> usize {
+        self.lights.len() + usize::from(self.ambient_light.is_sampled())
+    }
+
+    // next event estimation, samples a point on a random light or a direction towards
+    // the environment and weights it against finding the light by sampling the bsdf or
+    // phase function. `medium` is the one the ray arrived through
+    fn sample_light(&self, vertex: &Vertex, ray: &Ray, medium: Option<&(dyn Medium + Send + Sync)>, use_mis: bool) -> Color {
+        let mut rng = sample_rng();
+        let count = self.light_count();
+        if count == 0 { return Color::default() }
+        let light = rng.gen_range(0..count);
+        let pos = match vertex {
+            Vertex::Surface{intersection, ..} => intersection.pos,
+            Vertex::Medium{pos, ..} => *pos,
+        };
+
+        let (direction, distance, radiance, light_pdf) = if light == self.lights.len() {
+            let Some((direction, radiance, pdf)) = self.ambient_light.sample(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0))
+                else { return Color::default() };
+            (direction, f32::INFINITY, ray.to_spectrum(radiance), pdf / count as f32)
         } else {
-            self.ambient_light.get_color(ray)
+            let light = self.objs[self.lights[light]].as_ref();
+            let Some(light_intersection) = light.sample_surface(ray.time) else { return Color::default() };
+            let to_light = light_intersection.pos - pos;
+            let distance = to_light.len();
+            let direction = to_light.norm();
+            let shadow_ray = Ray{origin: pos, direction, ..ray.clone()};
+            let light_intersection = Intersection{t: distance, ..light_intersection};
+            let radiance = light.emitted(&light_intersection, &shadow_ray);
+            (direction, distance, radiance, self.light_pdf(light, &light_intersection, &pos, &direction))
+        };
+        if light_pdf <= 0.0 { return Color::default() }
+
+        let (value, pdf, medium) = match vertex {
+            Vertex::Surface{object, intersection} => {
+                if !Scene::is_consistent(intersection, ray, &direction) { return Color::default() }
+                let medium = if Scene::crosses(intersection, ray, &direction) {
+                    self.medium_behind(*object, intersection, &direction)
+                } else {
+                    medium
+                };
+                (object.eval(intersection, ray, &direction), object.pdf(intersection, ray, &direction), medium)
+            },
+            Vertex::Medium{phase, ..} => {
+                let p = phase.eval(ray.direction * direction);
+                (Color::new(p), p, medium)
+            }
+        };
+        if pdf <= 0.0 { return Color::default() }
+
+        let shadow_ray = Ray{origin: pos + RAY_START_EPSILON * direction, direction, ..ray.clone()};
+        let transmittance = self.transmittance(&shadow_ray, distance, medium);
+        let weight = if use_mis { power_heuristic(light_pdf, pdf) } else { 1.0 };
+        value * transmittance * radiance * (weight / light_pdf)
+    }
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::light::Emissive;
+    use crate::object::material::{Diffuse, Metalic, Plastic, Refractive, Interface};
+    use crate::spectrum::Ior;
+    use crate::medium::{Homogeneous, Heterogeneous};
+    use crate::object::plane::Plane;
+    use crate::object::microfacet::{fresnel_dielectric, reflect, refract, Ggx};
+    use crate::object::principled::Principled;
+    use crate::object::sphere::Sphere;
+    use crate::texture::Texture;
+    use crate::Shade;
+    use crate::vec3::{Vec3, Vec3n};
+
+    use std::sync::Arc;
+
+    // a scene in a uniform environment, the tests trace their own rays and never use the camera
+    fn builder(environment: f32) -> SceneBuilder {
+        let camera = Camera::new(90.0, &Vec3{x: 0.0, y: -3.0, z: 0.0}, &Vec3::default(), &Vec3n::new(0.0, 0.0, 1.0), 0.0);
+        Scene::builder(camera).ambient_light(AmbientLight::uniform(Color::new(environment)))
+    }
+
+    // a large plane through the origin facing up
+    fn floor(material: impl Shade + Sync + 'static) -> Plane {
+        floor_boxed(Box::new(material))
+    }
+
+    fn floor_boxed(material: Box<dyn Shade + Sync>) -> Plane {
+        Plane::new(Vec3::default(), Vec3n::new(0.0, 0.0, 1.0), (100.0, 100.0), material)
+    }
+
+    fn mean_radiance(scene: &Scene, ray: &Ray, n: usize) -> Color {
+        (0..n).map(|_| scene.trace(ray)).fold(Color::default(), |a, b| a + b) / n as f32
+    }
+
+    // mean red radiance and its standard error
+    fn radiance_with_error(scene: &Scene, ray: &Ray, n: usize) -> (f32, f32) {
+        let samples: Vec<f32> = (0..n).map(|_| scene.trace(ray).r).collect();
+        let mean = samples.iter().sum::<f32>() / n as f32;
+        let variance = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>() / (n - 1) as f32;
+        (mean, (variance / n as f32).sqrt())
+    }
+
+    // share of the light arriving from `wo` that a single scattering off a dielectric with
+    // the microfacets of `ggx` sends back out, integrated over the visible normals. Light
+    // that would hit another microfacet is lost, and so is the transmitted part unless
+    // `transmission` is set
+    fn single_scattering_albedo(ggx: &Ggx, wo: &Vec3, eta: f32, transmission: bool) -> f32 {
+        let steps = 256;
+        let mut albedo = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                let (u1, u2) = ((i as f32 + 0.5) / steps as f32, (j as f32 + 0.5) / steps as f32);
+                let h = ggx.sample_visible(wo, u1, u2);
+                let f = fresnel_dielectric(wo * h, eta);
+                let wr = reflect(wo, &h);
+                if wr.z > 0.0 {
+                    albedo += f * ggx.g(wo, &wr) / ggx.g1(wo);
+                }
+                match refract(wo, &h, eta) {
+                    Some(wt) if transmission && wt.z < 0.0 => albedo += (1.0 - f) * ggx.g(wo, &wt) / ggx.g1(wo),
+                    _ => (),
+                }
+            }
         }
+        albedo / (steps * steps) as f32
+    }
+
+    // straight down onto the floor
+    fn down() -> Ray {
+        Ray::new(Vec3{x: 0.0, y: 0.0, z: 0.5}, Vec3n::new(0.0, 0.0, -1.0))
+    }
+
+    // past the center of a unit sphere at the origin
+    fn towards_sphere() -> Ray {
+        Ray::new(Vec3{x: 0.3, y: -3.0, z: 0.2}, Vec3n::new(0.0, 1.0, 0.0))
+    }
+
+    // a diffuse plane below a large uniform emitter reflects albedo * radiance
+    #[test]
+    fn diffuse_plane_under_uniform_emitter() {
+        let albedo = 0.5;
+        let radiance = 2.0;
+        let scene = builder(0.0)
+            .object(floor(Diffuse::new(Arc::new(Color::new(albedo)))))
+            .object(Plane::new(Vec3{x: 0.0, y: 0.0, z: 1.0}, Vec3n::new(0.0, 0.0, -1.0), (100.0, 100.0),
+                Box::new(Emissive::new(Color::new(radiance), false))))
+            .build();
+        let sum = mean_radiance(&scene, &down(), 20000);
+
+        // the emitter covers all but ~0.01% of the cosine weighted hemisphere
+        let expected = albedo * radiance;
+        assert!((sum.r - expected).abs() < 0.02 * expected, "{} != {}", sum.r, expected);
     }
-}
\ No newline at end of file
+
+    // the emitter only shines on the side its normal points to
+    #[test]
+    fn one_sided_emitter() {
+        let scene = builder(0.0)
+            .object(Plane::new(Vec3::default(), Vec3n::new(0.0, 0.0, 1.0), (1.0, 1.0), Box::new(Emissive::new(Color::new(3.0), false))))
+            .build();
+        let front = Ray::new(Vec3{x: 0.0, y: 0.0, z: 1.0}, Vec3n::new(0.0, 0.0, -1.0));
+        let back = Ray::new(Vec3{x: 0.0, y: 0.0, z: -1.0}, Vec3n::new(0.0, 0.0, 1.0));
+        assert_eq!(scene.trace(&front).r, 3.0);
+        assert_eq!(scene.trace(&back).r, 0.0);
+    }
+
+    // a light stretched by its transform is as bright when it is sampled directly as when
+    // it is only found by the directions the bsdf samples
+    #[test]
+    fn stretched_emitter() {
+        crate::random::seed(1);
+        use crate::mat4::Mat4;
+        use crate::object::instance::Instance;
+        let albedo = 0.5;
+        let light = Plane::new(Vec3::default(), Vec3n::new(0.0, 0.0, -1.0), (1.0, 1.0), Box::new(Emissive::new(Color::new(4.0), false)));
+        let transform = Mat4::translation(&Vec3{x: 0.3, y: 0.2, z: 1.0})
+            * Mat4::rotation(&Vec3n::new(1.0, 0.0, 0.0), 20.0)
+            * Mat4::scaling(&Vec3{x: 3.0, y: 0.5, z: 1.0});
+        let scene = builder(0.0)
+            .object(floor(Diffuse::new(Arc::new(Color::new(albedo)))))
+            .object(Instance::new(Box::new(light), transform).unwrap())
+            .build();
+        assert!((scene.objs[1].area() - 1.5).abs() < 1e-4);
+        let sum = mean_radiance(&scene, &down(), 40000);
+
+        // cosine distributed directions above the floor, like the diffuse bsdf samples them
+        let mut rng = sample_rng();
+        let m = 40000;
+        let bsdf_only = (0..m).map(|_| {
+            let (r, phi) = (rng.gen_range(0.0f32..1.0).sqrt(), rng.gen_range(0.0..2.0 * std::f32::consts::PI));
+            let d = Vec3n::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt());
+            let ray = Ray::new(Vec3::default(), d);
+            scene.first_intersect(&ray).map_or(0.0, |(object, hit)| object.emitted(&hit, &ray).r)
+        }).sum::<f32>() / m as f32 * albedo;
+        assert!(bsdf_only > 0.1);
+        assert!((sum.r - bsdf_only).abs() < 0.03 * bsdf_only, "{} != {}", sum.r, bsdf_only);
+    }
+
+    // a textured emitter is as bright when it is sampled directly as when it is only found
+    // by the directions the bsdf samples, which needs the texture at the sampled point
+    #[test]
+    fn textured_emitter() {
+        crate::random::seed(1);
+        use crate::texture::Checker;
+        let albedo = 0.5;
+        let value = |v: f32| Arc::new(Color::new(v)) as Arc<dyn Texture + Send + Sync>;
+        let emission = Arc::new(Checker::new(value(0.0), value(4.0), 2.0));
+        let light = Principled{specular: value(0.0), emission: Some(emission), ..Principled::new(value(0.0))};
+        let scene = builder(0.0)
+            .object(floor(Diffuse::new(Arc::new(Color::new(albedo)))))
+            .object(Plane::new(Vec3{x: 0.0, y: 0.0, z: 1.0}, Vec3n::new(0.0, 0.0, -1.0), (2.0, 2.0), Box::new(light)))
+            .build();
+        let sum = mean_radiance(&scene, &down(), 40000);
+
+        let mut rng = sample_rng();
+        let m = 40000;
+        let bsdf_only = (0..m).map(|_| {
+            let (r, phi) = (rng.gen_range(0.0f32..1.0).sqrt(), rng.gen_range(0.0..2.0 * std::f32::consts::PI));
+            let d = Vec3n::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt());
+            let ray = Ray::new(Vec3::default(), d);
+            scene.first_intersect(&ray).map_or(0.0, |(object, hit)| object.emitted(&hit, &ray).r)
+        }).sum::<f32>() / m as f32 * albedo;
+        assert!(bsdf_only > 0.1);
+        assert!((sum.r - bsdf_only).abs() < 0.03 * bsdf_only, "{} != {}", sum.r, bsdf_only);
+    }
+
+    // when a lobe may not bounce any more, next event estimation at that vertex is not
+    // weighted and the light has to be left out if a bsdf sample finds it anyway, so a
+    // light seen only directly is as bright with the bounce allowed as without it
+    #[test]
+    fn direct_light_without_bounces() {
+        crate::random::seed(1);
+        fn compare(material: fn() -> Box<dyn Shade + Sync>, settings: PathSettings) {
+            let light = || Plane::new(Vec3{x: 0.0, y: 0.0, z: 1.0}, Vec3n::new(0.0, 0.0, -1.0), (4.0, 4.0),
+                Box::new(Emissive::new(Color::new(1.0), false)));
+            let full = builder(0.0).object(floor_boxed(material())).object(light()).build();
+            let limited = builder(0.0).object(floor_boxed(material())).object(light()).path_settings(settings).build();
+            let (a, b) = (mean_radiance(&full, &down(), 10000).r, mean_radiance(&limited, &down(), 10000).r);
+            assert!(a > 0.1 && (a - b).abs() < 0.04 * a, "{} != {}", a, b);
+        }
+        compare(|| Box::new(Metalic::from_f0(&Color::new(1.0)).with_roughness(0.8, 0.0)),
+            PathSettings{max_specular_depth: 0, ..PathSettings::default()});
+        compare(|| Box::new(Plastic::new(Arc::new(Color::new(1.0)), 1.5, 0.3)),
+            PathSettings{max_diffuse_depth: 0, ..PathSettings::default()});
+    }
+
+    // directions have to leave the actual surface on the side the shading normal puts them
+    #[test]
+    fn consistent_directions() {
+        let mut intersection = Intersection::new(Vec3::default(), Vec3n::new(0.0, 0.0, 1.0), &Vec3{x: 1.0, y: 0.0, z: 0.0}, (0.0, 0.0), 1.0);
+        intersection.normal = Vec3n::new(0.5, 0.0, 1.0);
+        let ray = Ray::new(Vec3{x: 0.0, y: 1.0, z: 1.0}, Vec3n::new(0.0, -1.0, -1.0));
+        for (direction, consistent) in [
+            (Vec3n::new(0.0, 0.0, 1.0), true),
+            (Vec3n::new(0.0, 1.0, -0.5), true),
+            // above the shading surface but below the actual one
+            (Vec3n::new(1.0, 0.0, -0.2), false),
+            // below the shading surface but above the actual one
+            (Vec3n::new(-1.0, 0.0, 0.2), false),
+            (Vec3n::new(1.0, 0.0, 0.0), false),
+        ] {
+            assert_eq!(Scene::is_consistent(&intersection, &ray, &direction), consistent, "{:?}", direction);
+        }
+    }
+
+    // the environment is importance sampled and combined with the bsdf samples
+    #[test]
+    fn diffuse_plane_under_uniform_environment() {
+        let scene = builder(2.0).object(floor(Diffuse::new(Arc::new(Color::new(0.5))))).build();
+        let sum = mean_radiance(&scene, &down(), 20000);
+        assert!((sum.r - 1.0).abs() < 0.02, "{} != 1", sum.r);
+    }
+
+    // a white rough conductor in a uniform environment only loses the energy of the
+    // multiple scattering between microfacets that is not simulated
+    #[test]
+    fn rough_metal_under_uniform_environment() {
+        crate::random::seed(1);
+        let scene = builder(1.0).object(floor(Metalic::from_f0(&Color::new(1.0)).with_roughness(0.4, 0.0))).build();
+        let ray = Ray::new(Vec3{x: 0.0, y: -0.5, z: 0.5}, Vec3n::new(0.0, 1.0, -1.0));
+        let sum = mean_radiance(&scene, &ray, 20000);
+        assert!(sum.r > 0.9 && sum.r < 1.01, "{} is not about 1", sum.r);
+    }
+
+    // a rough glass surface in a uniform environment sends out what its microfacets
+    // reflect and transmit at the first scattering, entering from above the surface or
+    // leaving from below it
+    #[test]
+    fn rough_glass_under_uniform_environment() {
+        crate::random::seed(1);
+        for roughness in [0.1, 0.3, 0.6] {
+            let glass = Refractive::from_ior(Ior::Constant(1.5)).with_roughness(roughness);
+            let scene = builder(1.0).object(floor(glass)).build();
+            let direction = Vec3n::new(0.0, 0.6, -0.8);
+            for (ray, eta) in [
+                (Ray::new(Vec3{x: 0.0, y: -0.6, z: 0.8}, direction), 1.5),
+                (Ray::new(Vec3{x: 0.0, y: 0.6, z: -0.8}, -direction), 1.0 / 1.5),
+            ] {
+                let expected = single_scattering_albedo(&Ggx::new(roughness, 0.0), &Vec3{x: 0.0, y: 0.6, z: 0.8}, eta, true);
+                let (sum, error) = radiance_with_error(&scene, &ray, 20000);
+                assert!((sum - expected).abs() < 4.0 * error + 2e-3, "{} != {} ± {} for roughness {}", sum, expected, error, roughness);
+            }
+        }
+    }
+
+    // a white plastic reflects what its coat reflects at the first scattering, the rest
+    // reaches the base and leaves through the coat again. The light that the coat reflects
+    // back onto the base is not simulated
+    #[test]
+    fn plastic_under_uniform_environment() {
+        crate::random::seed(1);
+        // the cosine weighted average of the fresnel term over the directions leaving the base
+        let steps = 1000;
+        let average = (0..steps).map(|i| {
+            let cos = (i as f32 + 0.5) / steps as f32;
+            fresnel_dielectric(cos, 1.5) * 2.0 * cos / steps as f32
+        }).sum::<f32>();
+        for roughness in [0.0, 0.3, 0.6] {
+            let scene = builder(1.0).object(floor(Plastic::new(Arc::new(Color::new(1.0)), 1.5, roughness))).build();
+            for ray in [down(), Ray::new(Vec3{x: 0.0, y: -0.5, z: 0.5}, Vec3n::new(0.0, 1.0, -1.0))] {
+                let wo = -1.0 * Vec3::from(ray.direction);
+                let coat = single_scattering_albedo(&Ggx::new(roughness, 0.0), &wo, 1.5, false);
+                let expected = coat + (1.0 - fresnel_dielectric(wo.z, 1.5)) * (1.0 - average);
+                let (sum, error) = radiance_with_error(&scene, &ray, 20000);
+                assert!((sum - expected).abs() < 4.0 * error + 2e-3, "{} != {} ± {} for roughness {}", sum, expected, error, roughness);
+            }
+        }
+    }
+
+    // the metal presets reflect the same color whether the measured spectrum is traced
+    // or its rgb reflectance
+    #[test]
+    fn metal_presets_match_spectral() {
+        crate::random::seed(1);
+        for metal in [Metalic::gold, Metalic::silver, Metalic::copper, Metalic::aluminium] {
+            let mut scene = builder(1.0).object(floor(metal())).build();
+            let rgb = scene.trace(&down());
+            scene.set_spectral(true);
+            let spectral = mean_radiance(&scene, &down(), 20000);
+            // about four standard deviations of a single spectral sample at this count
+            for (a, b) in [(rgb.r, spectral.r), (rgb.g, spectral.g), (rgb.b, spectral.b)] {
+                assert!((a - b).abs() < 0.03, "{:?} != {:?}", rgb, spectral);
+            }
+        }
+    }
+
+    // smooth white metal and clear glass made of the principled material neither gain
+    // nor lose energy in a uniform environment
+    #[test]
+    fn principled_sphere_under_uniform_environment() {
+        let value = |v: f32| Arc::new(Color::new(v)) as Arc<dyn Texture + Send + Sync>;
+        let metal = Principled{metallic: value(1.0), roughness: value(0.0), ..Principled::new(value(1.0))};
+        let glass = Principled{transmission: value(1.0), roughness: value(0.0), ..Principled::new(value(1.0))};
+        for material in [metal, glass] {
+            let scene = builder(1.0).object(Sphere::new(Vec3::default(), 1.0, Box::new(material))).build();
+            let sum = mean_radiance(&scene, &towards_sphere(), 20000);
+            assert!((sum.r - 1.0).abs() < 0.02, "{} != 1", sum.r);
+        }
+    }
+
+    // a medium that scatters without absorbing neither gains nor loses energy in a uniform
+    // environment, whatever its density
+    #[test]
+    fn scattering_media_under_uniform_environment() {
+        crate::random::seed(1);
+        let noise = Arc::new(crate::texture::Noise::new(Color::default(), Color::new(1.0), 2.0, 2, 1));
+        let media: [Arc<dyn Medium + Send + Sync>; 2] = [
+            Arc::new(Homogeneous::new(Color::default(), Color{r: 0.5, g: 1.0, b: 3.0}, 0.5)),
+            Arc::new(Heterogeneous::new(Color::default(), Color{r: 1.0, g: 2.0, b: 4.0}, noise, -0.3)),
+        ];
+        for medium in media {
+            let scene = builder(1.0).object(Sphere::new(Vec3::default(), 1.0, Box::new(Interface)).with_medium(medium)).build();
+            let sum = mean_radiance(&scene, &towards_sphere(), 4000);
+            for c in [sum.r, sum.g, sum.b] {
+                assert!((c - 1.0).abs() < 0.04, "{} != 1", c);
+            }
+        }
+    }
+
+    // the fog only fills the bounds of the scene, rays from a camera outside of them are
+    // attenuated along the part of their way through the box, whether they hit or escape
+    #[test]
+    fn fog_seen_from_outside() {
+        // the bounds are -1..1 along x and y and 0..2 along z
+        let scene = builder(1.0)
+            .object(Plane::new(Vec3::default(), Vec3n::new(0.0, 0.0, 1.0), (2.0, 2.0), Box::new(Emissive::new(Color::new(1.0), false))))
+            .object(Plane::new(Vec3{x: 0.9, y: 0.9, z: 2.0}, Vec3n::new(0.0, 0.0, -1.0), (0.2, 0.2), Box::new(Emissive::new(Color::default(), false))))
+            .fog(Arc::new(Homogeneous::new(Color::new(0.5), Color::default(), 0.0)))
+            .build();
+
+        let expected = f32::exp(-0.5 * 2.0);
+        let hit = Ray::new(Vec3{x: 0.0, y: 0.0, z: 10.0}, Vec3n::new(0.0, 0.0, -1.0));
+        let escaped = Ray::new(Vec3{x: -5.0, y: 0.0, z: 1.0}, Vec3n::new(1.0, 0.0, 0.0));
+        let missed = Ray::new(Vec3{x: -5.0, y: 0.0, z: 3.0}, Vec3n::new(1.0, 0.0, 0.0));
+        for (ray, expected) in [(hit, expected), (escaped, expected), (missed, 1.0)] {
+            let c = scene.trace(&ray);
+            assert!((c.r - expected).abs() < 1e-4, "{} != {}", c.r, expected);
+        }
+    }
+
+    // light passing straight through an absorbing sphere follows beer-lambert
+    #[test]
+    fn absorbing_medium() {
+        let medium = Arc::new(Homogeneous::absorbing(&Color{r: 0.8, g: 0.5, b: 0.2}, 1.0));
+        let scene = builder(1.0).object(Sphere::new(Vec3::default(), 1.0, Box::new(Interface)).with_medium(medium)).build();
+        let c = scene.trace(&Ray::new(Vec3{x: 0.0, y: -3.0, z: 0.0}, Vec3n::new(0.0, 1.0, 0.0)));
+        for (value, expected) in [(c.r, 0.64), (c.g, 0.25), (c.b, 0.04)] {
+            assert!((value - expected).abs() < 1e-3, "{} != {}", value, expected);
+        }
+    }
+}
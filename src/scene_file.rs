@@ -0,0 +1,1076 @@
+use crate::camera::{Camera, Lens};
+use crate::color::Color;
+use crate::error::Error;
+use crate::image::{Image, Resolution, Wrap};
+use crate::texture::{Texture, ImageTexture, Checker, Noise};
+use crate::spectrum::Ior;
+use crate::object::Object;
+use crate::object::light::{AmbientLight, Emissive};
+use crate::object::material::{Diffuse, Metalic, Refractive, Plastic, Interface, Perturbed, Perturbation};
+use crate::object::principled::Principled;
+use crate::object::{Shade, sphere::Sphere, plane::Plane, mesh::Mesh, instance::{Instance, InstanceError}};
+use crate::obj_file::{self, ObjModel};
+use crate::mat4::Mat4;
+use crate::motion::{Motion, Keyframe};
+use crate::animation::{self, Interpolation};
+use crate::medium::{Medium, Homogeneous, Heterogeneous};
+use crate::scene::Scene;
+use crate::vec3::{Vec3, Vec3n};
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+// Scene files are made of sections, each started by a `[kind name]` header and
+// followed by `key = value` lines. `# ` starts a comment.
+//
+//   [camera]
+//   eye = 0 -10 3
+//
+//   [material glass]
+//   type = refractive
+//   ior = 1.5
+//
+//   [texture tiles]
+//   type = checker
+//   even = #FFFFFF
+//   odd = 0.1
+//
+//   [medium smoke]
+//   type = heterogeneous
+//   scattering = 2
+//   density = clouds
+//
+//   [sphere]
+//   center = 0 0 3
+//   radius = 3
+//   material = glass
+//
+// `key @ time = value` lines are keyframes of a value that changes over time
+//
+//   [material light]
+//   radiance @ 0 = 2
+//   radiance @ 2 bezier = 10
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub key: String,
+    pub reason: String,
+    // why a file the scene refers to could not be loaded
+    pub source: Option<Box<Error>>,
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}:{}: {}", self.file.display(), self.line, self.reason)
+        } else {
+            write!(f, "{}:{}: `{}`: {}", self.file.display(), self.line, self.key, self.reason)
+        }
+    }
+}
+
+struct Entry {
+    key: String,
+    value: String,
+    line: usize,
+}
+
+// the keyframes of a key with their time and the interpolation towards the next one
+type Track = (String, Vec<(f32, Interpolation, Entry)>);
+
+// the time of a keyframe in both `key @ time` tracks and `keyframe = time: steps` motion,
+// optionally followed by the interpolation towards the next keyframe
+fn keyframe_time(s: &str) -> Option<(f32, Interpolation)> {
+    let mut words = s.split_whitespace();
+    let time = words.next()?.parse().ok()?;
+    let interpolation = match words.next() {
+        Some(word) => word.parse().ok()?,
+        None => Interpolation::Linear,
+    };
+    if words.next().is_some() { return None }
+    Some((time, interpolation))
+}
+
+const INVALID_KEYFRAME_TIME: &str = "invalid keyframe time, use a number optionally followed by linear or bezier";
+
+struct Section<'a> {
+    file: &'a Path,
+    kind: String,
+    name: Option<String>,
+    line: usize,
+    entries: Vec<Entry>,
+}
+
+impl<'a> Section<'a> {
+    fn error(&self, line: usize, key: &str, reason: impl Into<String>) -> ParseError {
+        ParseError{file: self.file.to_path_buf(), line, key: key.to_string(), reason: reason.into(), source: None}
+    }
+
+    // the file named by `key` could not be loaded
+    fn load_error(&self, key: &str, source: Error) -> ParseError {
+        ParseError{source: Some(Box::new(source)), ..self.error(self.entry(key).unwrap().line, key, "could not load")}
+    }
+
+    fn entry(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().rev().find(|e| e.key == key)
+    }
+
+    fn expect_keys(&self, keys: &[&str]) -> Result<(), ParseError> {
+        match self.entries.iter().find(|e| !keys.contains(&e.key.as_str())) {
+            Some(e) => Err(self.error(e.line, &e.key, format!("unknown key in [{}]", self.kind))),
+            None => Ok(())
+        }
+    }
+
+    fn str(&self, key: &str) -> Result<&str, ParseError> {
+        self.entry(key)
+            .map(|e| e.value.as_str())
+            .ok_or_else(|| self.error(self.line, key, format!("missing in [{}]", self.kind)))
+    }
+
+    fn parse<T: FromStr>(&self, key: &str) -> Result<T, ParseError> {
+        let value = self.str(key)?;
+        value.parse().map_err(|_| self.error(self.entry(key).unwrap().line, key, format!("invalid value `{}`", value)))
+    }
+
+    fn parse_or<T: FromStr>(&self, key: &str, default: T) -> Result<T, ParseError> {
+        if self.entry(key).is_some() { self.parse(key) } else { Ok(default) }
+    }
+
+    fn floats(&self, key: &str, count: usize) -> Result<Vec<f32>, ParseError> {
+        let entry = self.entry(key).ok_or_else(|| self.error(self.line, key, format!("missing in [{}]", self.kind)))?;
+        let values: Result<Vec<f32>, _> = entry.value.split_whitespace().map(f32::from_str).collect();
+        match values {
+            Ok(values) if values.len() == count => Ok(values),
+            _ => Err(self.error(entry.line, key, format!("expected {} numbers, got `{}`", count, entry.value)))
+        }
+    }
+
+    // a number greater than zero, also accepted as a fraction like `1/125`
+    fn positive_or(&self, key: &str, default: f32) -> Result<f32, ParseError> {
+        let entry = match self.entry(key) {
+            Some(entry) => entry,
+            None => return Ok(default)
+        };
+        let value = match entry.value.split_once('/') {
+            Some((a, b)) => a.trim().parse::<f32>().and_then(|a| b.trim().parse::<f32>().map(|b| a / b)),
+            None => entry.value.parse::<f32>()
+        };
+        match value {
+            Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+            _ => Err(self.error(entry.line, key, format!("expected a positive number, got `{}`", entry.value)))
+        }
+    }
+
+    fn vec3(&self, key: &str) -> Result<Vec3, ParseError> {
+        let v = self.floats(key, 3)?;
+        Ok(Vec3{x: v[0], y: v[1], z: v[2]})
+    }
+
+    fn vec3_or(&self, key: &str, default: Vec3) -> Result<Vec3, ParseError> {
+        if self.entry(key).is_some() { self.vec3(key) } else { Ok(default) }
+    }
+
+    fn direction(&self, key: &str) -> Result<Vec3n, ParseError> {
+        let v = self.vec3(key)?;
+        if v.len_squared() == 0.0 {
+            return Err(self.error(self.entry(key).unwrap().line, key, "direction must not be zero"))
+        }
+        Ok(Vec3n::from(v))
+    }
+
+    // colors are either `#RRGGBB`, a single grey value or three floats,
+    // optionally followed by `* <scale>`
+    fn color(&self, key: &str) -> Result<Color, ParseError> {
+        let entry = self.entry(key).ok_or_else(|| self.error(self.line, key, format!("missing in [{}]", self.kind)))?;
+        let invalid = || self.error(entry.line, key, format!("invalid color `{}`", entry.value));
+        let (color, scale) = match entry.value.split_once('*') {
+            Some((color, scale)) => (color.trim(), scale.trim().parse::<f32>().map_err(|_| invalid())?),
+            None => (entry.value.as_str(), 1.0)
+        };
+        let color = if color.starts_with('#') {
+            Color::from_hex(color).map_err(|e| self.error(entry.line, key, e.to_string()))?
+        } else {
+            let values: Vec<f32> = color.split_whitespace().map(f32::from_str).collect::<Result<_, _>>().map_err(|_| invalid())?;
+            match values[..] {
+                [a] => Color::new(a),
+                [r, g, b] => Color{r, g, b},
+                _ => return Err(invalid())
+            }
+        };
+        Ok(color * scale)
+    }
+
+    fn color_or(&self, key: &str, default: Color) -> Result<Color, ParseError> {
+        if self.entry(key).is_some() { self.color(key) } else { Ok(default) }
+    }
+
+    // the name of a texture or a color
+    fn texture(&self, key: &str, textures: &Textures) -> Result<Arc<dyn Texture + Send + Sync>, ParseError> {
+        match textures.get(self.str(key)?) {
+            Some(texture) => Ok(texture.clone()),
+            None => Ok(Arc::new(self.color(key)?))
+        }
+    }
+
+    fn texture_or(&self, key: &str, default: Arc<dyn Texture + Send + Sync>, textures: &Textures) -> Result<Arc<dyn Texture + Send + Sync>, ParseError> {
+        match self.entry(key) {
+            Some(_) => self.texture(key, textures),
+            None => Ok(default)
+        }
+    }
+
+    // a list of steps applied from left to right, like `rotate z 45, scale 2, translate 0 0 1`.
+    // Rotations are in degrees around x, y, z or an axis given by three numbers
+    fn transform(&self, key: &str) -> Result<Option<Mat4>, ParseError> {
+        let Some(entry) = self.entry(key) else { return Ok(None) };
+        let invalid = |step: &str| self.error(entry.line, key, format!("invalid step `{}`, use translate, scale or rotate", step.trim()));
+        let mut transform = Mat4::identity();
+        for step in entry.value.split(',') {
+            let mut words = step.split_whitespace();
+            let op = words.next().ok_or_else(|| invalid(step))?;
+            let args: Vec<&str> = words.collect();
+            let numbers = |args: &[&str]| args.iter().map(|a| a.parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|_| invalid(step));
+            let matrix = match (op, &args[..]) {
+                ("translate", _) => match numbers(&args)?[..] {
+                    [x, y, z] => Mat4::translation(&Vec3{x, y, z}),
+                    _ => return Err(invalid(step))
+                },
+                ("scale", _) => match numbers(&args)?[..] {
+                    [s] => Mat4::scaling(&Vec3::from(s)),
+                    [x, y, z] => Mat4::scaling(&Vec3{x, y, z}),
+                    _ => return Err(invalid(step))
+                },
+                ("rotate", [axis @ ("x" | "y" | "z"), angle]) => {
+                    let axis = match *axis {
+                        "x" => Vec3n::new(1.0, 0.0, 0.0),
+                        "y" => Vec3n::new(0.0, 1.0, 0.0),
+                        _ => Vec3n::new(0.0, 0.0, 1.0),
+                    };
+                    Mat4::rotation(&axis, numbers(&[angle])?[0])
+                },
+                ("rotate", _) => match numbers(&args)?[..] {
+                    [x, y, z, angle] if x != 0.0 || y != 0.0 || z != 0.0 => Mat4::rotation(&Vec3n::new(x, y, z), angle),
+                    _ => return Err(invalid(step))
+                },
+                _ => return Err(invalid(step))
+            };
+            transform = matrix * transform;
+        }
+        Ok(Some(transform))
+    }
+
+    // every `key` line is a keyframe `time: step, ...` with the steps `translate x y z`,
+    // `rotate x y z` (degrees around the axes) and `scale s` or `scale x y z`. The time may
+    // be followed by the interpolation towards the next keyframe, linear or bezier
+    fn motion(&self, key: &str) -> Result<Option<Motion>, ParseError> {
+        let mut keyframes = Vec::new();
+        for entry in self.entries.iter().filter(|e| e.key == key) {
+            let invalid = |what: &str| self.error(entry.line, key, format!("invalid {} in `{}`", what, entry.value));
+            let (time, steps) = entry.value.split_once(':').ok_or_else(|| invalid("keyframe, use `time: steps`"))?;
+            let (time, interpolation) = keyframe_time(time).ok_or_else(|| self.error(entry.line, key, INVALID_KEYFRAME_TIME))?;
+            let mut keyframe = Keyframe{interpolation, ..Keyframe::new(time)};
+            for step in steps.split(',').filter(|step| !step.trim().is_empty()) {
+                let mut words = step.split_whitespace();
+                let op = words.next().unwrap_or("");
+                let numbers = words.map(|a| a.parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|_| invalid("step"))?;
+                match (op, &numbers[..]) {
+                    ("translate", &[x, y, z]) => keyframe.translation = Vec3{x, y, z},
+                    ("rotate", &[x, y, z]) => keyframe.rotation = Vec3{x, y, z},
+                    ("scale", &[s]) => keyframe.scale = Vec3::from(s),
+                    ("scale", &[x, y, z]) => keyframe.scale = Vec3{x, y, z},
+                    _ => return Err(invalid("step, use translate, rotate or scale"))
+                }
+            }
+            keyframes.push(keyframe);
+        }
+        Ok(if keyframes.is_empty() { None } else { Some(Motion::new(keyframes)) })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, ParseError> {
+        let path = PathBuf::from(self.str(key)?);
+        Ok(match self.file.parent() {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path
+        })
+    }
+
+    // `key @ time = value` lines are keyframes of `key`, the time may be followed by the
+    // interpolation towards the next keyframe (`radiance @ 2 bezier = 10`). They are
+    // replaced by the value at `time`, numbers are interpolated and everything else has to
+    // be the same in all keyframes
+    fn animate(&mut self, time: f32) -> Result<(), ParseError> {
+        let mut entries = Vec::new();
+        let mut tracks: Vec<Track> = Vec::new();
+        for entry in std::mem::take(&mut self.entries) {
+            let Some((key, keyframe)) = entry.key.split_once('@') else {
+                entries.push(entry);
+                continue
+            };
+            let Some((t, interpolation)) = keyframe_time(keyframe) else {
+                return Err(self.error(entry.line, &entry.key, INVALID_KEYFRAME_TIME))
+            };
+            let key = key.trim().to_string();
+            match tracks.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, keyframes)) => keyframes.push((t, interpolation, entry)),
+                None => tracks.push((key, vec![(t, interpolation, entry)])),
+            }
+        }
+
+        for (key, mut keyframes) in tracks {
+            if let Some(entry) = entries.iter().find(|e| e.key == key) {
+                return Err(self.error(entry.line, &key, "has keyframes as well as a value"))
+            }
+            keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+            // words of the values with the commas that follow them
+            let split = |value: &str| value.split_whitespace().map(|word| {
+                let number = word.trim_end_matches(',');
+                (number.to_string(), word[number.len()..].to_string())
+            }).collect::<Vec<_>>();
+            let values: Vec<_> = keyframes.iter().map(|(_, _, entry)| split(&entry.value)).collect();
+            for ((_, _, entry), words) in keyframes.iter().zip(values.iter()) {
+                let same_shape = words.len() == values[0].len() && words.iter().zip(values[0].iter()).all(|(a, b)| {
+                    a.1 == b.1 && match (a.0.parse::<f32>(), b.0.parse::<f32>()) { (Ok(_), Ok(_)) => true, (Err(_), Err(_)) => a.0 == b.0, _ => false }
+                });
+                if !same_shape {
+                    return Err(self.error(entry.line, &entry.key, "keyframes may only differ in their numbers"))
+                }
+            }
+
+            let times: Vec<f32> = keyframes.iter().map(|k| k.0).collect();
+            let interpolations: Vec<Interpolation> = keyframes.iter().map(|k| k.1).collect();
+            let weights = animation::weights(&times, &interpolations, time);
+            let value = (0..values[0].len()).map(|w| {
+                let (word, separator) = &values[0][w];
+                let word = if word.parse::<f32>().is_ok() {
+                    let value = weights.iter().map(|&(i, weight)| weight * values[i][w].0.parse::<f32>().unwrap()).sum::<f32>();
+                    // whole numbers like sample counts stay whole
+                    if values.iter().all(|words| words[w].0.parse::<i64>().is_ok()) {
+                        (value.round() as i64).to_string()
+                    } else {
+                        value.to_string()
+                    }
+                } else {
+                    word.clone()
+                };
+                word + separator
+            }).collect::<Vec<_>>().join(" ");
+            entries.push(Entry{key, value, line: keyframes[weights[0].0].2.line});
+        }
+        self.entries = entries;
+        Ok(())
+    }
+}
+
+fn tokenize<'a>(file: &'a Path, source: &str) -> Result<Vec<Section<'a>>, ParseError> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let line_nr = idx + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() { continue }
+
+        let error = |key: &str, reason: &str| ParseError{
+            file: file.to_path_buf(), line: line_nr, key: key.to_string(), reason: reason.to_string(), source: None
+        };
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.strip_suffix(']').ok_or_else(|| error("", "unterminated section header"))?;
+            let mut words = header.split_whitespace();
+            let kind = words.next().ok_or_else(|| error("", "empty section header"))?.to_string();
+            let name = words.next().map(str::to_string);
+            if words.next().is_some() {
+                return Err(error(&kind, "section header takes at most a kind and a name"))
+            }
+            sections.push(Section{file, kind, name, line: line_nr, entries: Vec::new()});
+        } else {
+            let (key, value) = line.split_once('=').ok_or_else(|| error(line, "expected `key = value`"))?;
+            let key = key.trim();
+            let section = sections.last_mut().ok_or_else(|| error(key, "key outside of a section"))?;
+            section.entries.push(Entry{key: key.to_string(), value: value.trim().to_string(), line: line_nr});
+        }
+    }
+    Ok(sections)
+}
+
+// a `#` directly followed by text is a hex color, not a comment
+fn strip_comment(line: &str) -> &str {
+    match line.char_indices().find(|&(i, c)| c == '#' && line[i+1..].chars().next().is_none_or(char::is_whitespace)) {
+        Some((i, _)) => &line[..i],
+        None if line.trim_start().starts_with('#') => "",
+        None => line
+    }
+}
+
+type Textures = HashMap<String, Arc<dyn Texture + Send + Sync>>;
+
+fn build_texture(section: &Section, textures: &Textures) -> Result<Arc<dyn Texture + Send + Sync>, ParseError> {
+    match section.str("type")? {
+        "image" => {
+            section.expect_keys(&["type", "file", "wrap", "scale", "linear"])?;
+            let path = section.path("file")?;
+            let load = if section.parse_or("linear", false)? { Image::load_linear } else { Image::load };
+            let image = load(&path).map_err(|e| section.load_error("file", e))?;
+            let wrap = match section.entry("wrap").map(|e| e.value.as_str()) {
+                None | Some("repeat") => Wrap::Repeat,
+                Some("clamp") => Wrap::Clamp,
+                Some("mirror") => Wrap::Mirror,
+                Some(other) => return Err(section.error(section.entry("wrap").unwrap().line, "wrap",
+                    format!("unknown wrap mode `{}`, use repeat, clamp or mirror", other)))
+            };
+            Ok(Arc::new(ImageTexture::new(Arc::new(image), wrap, section.parse_or("scale", 1.0)?)))
+        },
+        "checker" => {
+            section.expect_keys(&["type", "even", "odd", "scale"])?;
+            Ok(Arc::new(Checker::new(section.texture("even", textures)?, section.texture("odd", textures)?, section.parse_or("scale", 8.0)?)))
+        },
+        "noise" => {
+            section.expect_keys(&["type", "low", "high", "scale", "octaves", "seed"])?;
+            Ok(Arc::new(Noise::new(
+                section.color_or("low", Color::default())?,
+                section.color_or("high", Color::new(1.0))?,
+                section.parse_or("scale", 1.0)?,
+                section.parse_or("octaves", 1)?,
+                section.parse_or("seed", 0)?,
+            )))
+        },
+        other => Err(section.error(section.entry("type").unwrap().line, "type", format!("unknown texture type `{}`", other)))
+    }
+}
+
+// keys every material type accepts
+const MATERIAL_KEYS: &[&str] = &["type", "normal_map", "bump", "bump_strength"];
+
+// `area` is the surface area of the object the material is used for
+fn build_material(section: &Section, area: f32, textures: &Textures) -> Result<Box<dyn Shade + Sync>, ParseError> {
+    let material = build_base_material(section, area, textures)?;
+    let named_texture = |key: &str| {
+        let name = section.str(key)?;
+        textures.get(name).cloned().ok_or_else(|| section.error(section.entry(key).unwrap().line, key, format!("unknown texture `{}`", name)))
+    };
+    match (section.entry("normal_map"), section.entry("bump")) {
+        (None, None) => Ok(material),
+        (Some(_), None) => Ok(Box::new(Perturbed::new(material, Perturbation::NormalMap(named_texture("normal_map")?)))),
+        (None, Some(_)) => Ok(Box::new(Perturbed::new(material, Perturbation::Bump{
+            height: named_texture("bump")?,
+            strength: section.parse_or("bump_strength", 1.0)?,
+        }))),
+        (Some(_), Some(_)) => Err(section.error(section.entry("bump").unwrap().line, "bump", "use either `normal_map` or `bump`"))
+    }
+}
+
+fn build_base_material(section: &Section, area: f32, textures: &Textures) -> Result<Box<dyn Shade + Sync>, ParseError> {
+    let kind = section.str("type")?;
+    match kind {
+        "diffuse" => {
+            section.expect_keys(&[MATERIAL_KEYS, &["diffuse"]].concat())?;
+            Ok(Box::new(Diffuse::new(section.texture("diffuse", textures)?)))
+        },
+        "plastic" => {
+            section.expect_keys(&[MATERIAL_KEYS, &["diffuse", "ior", "roughness"]].concat())?;
+            Ok(Box::new(Plastic::new(
+                section.texture("diffuse", textures)?,
+                section.parse_or("ior", 1.5)?,
+                section.parse_or("roughness", 0.1)?,
+            )))
+        },
+        "principled" => {
+            section.expect_keys(&[MATERIAL_KEYS, &[
+                "base_color", "metallic", "roughness", "specular", "transmission",
+                "clearcoat", "clearcoat_roughness", "sheen", "emission",
+            ]].concat())?;
+            let defaults = Principled::new(section.texture_or("base_color", Arc::new(Color::new(0.8)), textures)?);
+            Ok(Box::new(Principled {
+                metallic: section.texture_or("metallic", defaults.metallic.clone(), textures)?,
+                roughness: section.texture_or("roughness", defaults.roughness.clone(), textures)?,
+                specular: section.texture_or("specular", defaults.specular.clone(), textures)?,
+                transmission: section.texture_or("transmission", defaults.transmission.clone(), textures)?,
+                clearcoat: section.texture_or("clearcoat", defaults.clearcoat.clone(), textures)?,
+                clearcoat_roughness: section.texture_or("clearcoat_roughness", defaults.clearcoat_roughness.clone(), textures)?,
+                sheen: section.texture_or("sheen", defaults.sheen.clone(), textures)?,
+                emission: section.entry("emission").map(|_| section.texture("emission", textures)).transpose()?,
+                ..defaults
+            }))
+        },
+        "metal" => {
+            section.expect_keys(&[MATERIAL_KEYS, &["preset", "n", "k", "f0", "roughness", "anisotropy"]].concat())?;
+            let metal = if section.entry("f0").is_some() {
+                Metalic::from_texture(section.texture("f0", textures)?)
+            } else if section.entry("preset").is_some() {
+                match section.str("preset")? {
+                    "gold" => Metalic::gold(),
+                    "silver" => Metalic::silver(),
+                    "copper" => Metalic::copper(),
+                    "aluminium" => Metalic::aluminium(),
+                    other => return Err(section.error(section.entry("preset").unwrap().line, "preset", format!("unknown metal `{}`", other)))
+                }
+            } else {
+                Metalic::new(&section.color("n")?, &section.color("k")?)
+            };
+            Ok(Box::new(metal.with_roughness(section.parse_or("roughness", 0.0)?, section.parse_or("anisotropy", 0.0)?)))
+        },
+        "refractive" => {
+            section.expect_keys(&[MATERIAL_KEYS, &["ior", "cauchy", "sellmeier", "roughness"]].concat())?;
+            let glass = match (section.entry("ior"), section.entry("cauchy"), section.entry("sellmeier")) {
+                (Some(_), None, None) => match section.str("ior")? {
+                    "bk7" => Refractive::from_ior(Ior::bk7()),
+                    "fused_silica" => Refractive::from_ior(Ior::fused_silica()),
+                    "sf11" => Refractive::from_ior(Ior::sf11()),
+                    "diamond" => Refractive::from_ior(Ior::diamond()),
+                    _ => Refractive::new(&section.color("ior")?),
+                },
+                (None, Some(_), None) => {
+                    let c = section.floats("cauchy", 2)?;
+                    Refractive::from_ior(Ior::Cauchy{a: c[0], b: c[1]})
+                },
+                (None, None, Some(_)) => {
+                    let s = section.floats("sellmeier", 6)?;
+                    Refractive::from_ior(Ior::Sellmeier{b: [s[0], s[1], s[2]], c: [s[3], s[4], s[5]]})
+                },
+                (None, None, None) => Refractive::glass(),
+                _ => return Err(section.error(section.line, "ior", "use only one of `ior`, `cauchy` or `sellmeier`"))
+            };
+            Ok(Box::new(glass.with_roughness(section.parse_or("roughness", 0.0)?)))
+        },
+        "emissive" => {
+            section.expect_keys(&[MATERIAL_KEYS, &["radiance", "power", "color", "two_sided"]].concat())?;
+            let two_sided = section.parse_or("two_sided", false)?;
+            match (section.entry("radiance"), section.entry("power")) {
+                (Some(_), None) => Ok(Box::new(Emissive::new(section.color("radiance")?, two_sided))),
+                (None, Some(_)) => Ok(Box::new(Emissive::from_power(
+                    section.color_or("color", Color::new(1.0))?, section.parse("power")?, area, two_sided
+                ))),
+                _ => Err(section.error(section.line, "radiance", "emissive materials need either `radiance` or `power` (in watts)"))
+            }
+        },
+        other => Err(section.error(section.entry("type").unwrap().line, "type", format!("unknown material type `{}`", other)))
+    }
+}
+
+type Media = HashMap<String, Arc<dyn Medium + Send + Sync>>;
+
+fn build_medium(section: &Section, textures: &Textures) -> Result<Arc<dyn Medium + Send + Sync>, ParseError> {
+    let anisotropy = section.parse_or("anisotropy", 0.0)?;
+    match section.str("type")? {
+        "homogeneous" => {
+            section.expect_keys(&["type", "absorption", "scattering", "anisotropy", "color", "distance"])?;
+            if section.entry("color").is_some() {
+                if section.entry("absorption").is_some() || section.entry("scattering").is_some() {
+                    return Err(section.error(section.line, "color", "use either `color` and `distance` or `absorption` and `scattering`"))
+                }
+                let distance: f32 = section.parse_or("distance", 1.0)?;
+                if distance <= 0.0 {
+                    return Err(section.error(section.entry("distance").unwrap().line, "distance", "must be positive"))
+                }
+                let color = section.color("color")?;
+                if [color.r, color.g, color.b].iter().any(|c| !(*c > 0.0 && *c <= 1.0)) {
+                    return Err(section.error(section.entry("color").unwrap().line, "color", "is what is left of the light and has to be in (0, 1]"))
+                }
+                return Ok(Arc::new(Homogeneous::absorbing(&color, distance)))
+            }
+            Ok(Arc::new(Homogeneous::new(
+                section.color_or("absorption", Color::default())?,
+                section.color_or("scattering", Color::default())?,
+                anisotropy,
+            )))
+        },
+        "heterogeneous" => {
+            section.expect_keys(&["type", "absorption", "scattering", "anisotropy", "density"])?;
+            let density = section.texture("density", textures)?;
+            if !density.is_solid() {
+                return Err(section.error(section.entry("density").unwrap().line, "density",
+                    "needs a noise texture or a constant, uv mapped textures do not vary inside a medium"))
+            }
+            Ok(Arc::new(Heterogeneous::new(
+                section.color_or("absorption", Color::default())?,
+                section.color_or("scattering", Color::default())?,
+                density,
+                anisotropy,
+            )))
+        },
+        other => Err(section.error(section.entry("type").unwrap().line, "type", format!("unknown medium type `{}`", other)))
+    }
+}
+
+fn lookup_medium(section: &Section, media: &Media) -> Result<Option<Arc<dyn Medium + Send + Sync>>, ParseError> {
+    let Some(entry) = section.entry("medium") else { return Ok(None) };
+    media.get(&entry.value).cloned().map(Some).ok_or_else(|| {
+        section.error(entry.line, "medium", format!("unknown medium `{}`", entry.value))
+    })
+}
+
+// wraps the object in an instance if it has a transform
+// keyframes move the object in its own space, before the transform
+fn place(section: &Section, object: Box<dyn Object + Sync>, transform: Option<Mat4>, motion: &Option<Motion>) -> Result<Box<dyn Object + Sync>, ParseError> {
+    if transform.is_none() && motion.is_none() {
+        return Ok(object)
+    }
+    let instance = Instance::new(object, transform.unwrap_or_default()).map_err(|e| {
+        let line = section.entry("transform").map_or(section.line, |e| e.line);
+        section.error(line, "transform", e.to_string())
+    })?;
+    Ok(match motion {
+        Some(motion) => Box::new(instance.with_motion(motion.clone())),
+        None => Box::new(instance)
+    })
+}
+
+// the area of a shape once `place` applied its transform, flat shapes pass their normal
+// and keep an exact area under any transform, curved ones only under uniform scaling
+fn placed_area(area: f32, transform: Option<Mat4>, normal: Option<&Vec3n>) -> Option<f32> {
+    match (transform, normal) {
+        (None, _) => Some(area),
+        (Some(transform), Some(normal)) => Some(area * transform.area_scale(normal)),
+        (Some(transform), None) => transform.uniform_scale().map(|s| s * s * area),
+    }
+}
+
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.scene");
+
+// a scene with the render settings of its file
+pub struct SceneFile {
+    pub scene: Scene,
+    pub samples: Option<u32>,
+    pub resolution: Option<Resolution>,
+}
+
+impl SceneFile {
+    pub fn load(path: &Path) -> Result<SceneFile, Error> {
+        SceneFile::load_at(path, 0.0)
+    }
+
+    // the scene with its keyframe tracks evaluated at `time` in seconds
+    pub fn load_at(path: &Path, time: f32) -> Result<SceneFile, Error> {
+        let source = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        Ok(parse_at(path, &source, time)?)
+    }
+}
+
+// the built-in scene of scenes/default.scene
+impl Default for SceneFile {
+    fn default() -> Self {
+        parse(Path::new("default.scene"), DEFAULT_SCENE).unwrap()
+    }
+}
+
+fn lookup_material<'s, 'f>(section: &Section, key: &str, name: &str, materials: &HashMap<String, &'s Section<'f>>) -> Result<&'s Section<'f>, ParseError> {
+    materials.get(name).copied().ok_or_else(|| {
+        section.error(section.entry(key).unwrap().line, key, format!("unknown material `{}`", name))
+    })
+}
+
+pub fn parse(file: &Path, source: &str) -> Result<SceneFile, ParseError> {
+    parse_at(file, source, 0.0)
+}
+
+// the scene at `time` in seconds, for the frames of an animation
+pub fn parse_at(file: &Path, source: &str, time: f32) -> Result<SceneFile, ParseError> {
+    let mut sections = tokenize(file, source)?;
+    for section in sections.iter_mut() {
+        section.animate(time)?;
+    }
+
+    let mut materials: HashMap<String, &Section> = HashMap::new();
+    let mut textures: Textures = HashMap::new();
+    let mut media: Media = HashMap::new();
+    let mut fog = None;
+    // OBJ files placed several times are loaded once and share their triangles
+    let mut obj_models: HashMap<(PathBuf, u32, [u32; 3]), Vec<ObjModel>> = HashMap::new();
+    let mut camera = None;
+    let mut ambient_light = None;
+    let mut samples = None;
+    let mut resolution = None;
+    let mut spectral = false;
+    let mut objs: Vec<Box<dyn Object + Sync>> = Vec::new();
+
+    // emitters given by their power need the area of the object once it is placed, None if
+    // its transform stretches it in a way that changes the area unevenly
+    let object = |section: &Section, material: &Section, textures: &Textures, area: Option<f32>, build: &dyn Fn(Box<dyn Shade + Sync>) -> Result<Box<dyn Object + Sync>, ParseError>| {
+        let area = match area {
+            _ if material.entry("power").is_none() => 0.0,
+            Some(area) if area > 0.0 => area,
+            Some(_) => return Err(section.error(section.line, "material", "emitters given by power need an object with a finite area")),
+            None => return Err(section.error(section.entry("transform").map_or(section.line, |e| e.line), "transform", InstanceError::StretchedEmitter.to_string())),
+        };
+        build(build_material(material, area, textures)?)
+    };
+
+    for section in sections.iter() {
+        if section.name.is_some() && !["material", "texture", "medium"].contains(&section.kind.as_str()) {
+            return Err(section.error(section.line, &section.kind, "only materials, textures and media can be named"))
+        }
+        match section.kind.as_str() {
+            "render" => {
+                section.expect_keys(&["samples", "resolution", "spectral"])?;
+                if section.entry("samples").is_some() { samples = Some(section.parse("samples")?) }
+                if section.entry("resolution").is_some() { resolution = Some(section.parse("resolution")?) }
+                spectral = section.parse_or("spectral", false)?;
+            },
+            "camera" => {
+                section.expect_keys(&[
+                    "fov", "eye", "target", "up", "aperture", "focus_distance", "blades", "blade_rotation",
+                    "focal_length", "sensor", "f_stop", "iso", "shutter", "projection", "time", "keyframe",
+                ])?;
+                let eye = section.vec3("eye")?;
+                let target = section.vec3("target")?;
+                let up = Vec3n::from(section.vec3_or("up", Vec3{x: 0.0, y: 0.0, z: 1.0})?);
+                let physical = ["focal_length", "sensor", "f_stop", "iso"].iter().any(|key| section.entry(key).is_some());
+                // the shutter exposes a physical camera and blurs moving objects alike, a camera
+                // exposed by `iso` alone is open for 1/125 s, others only blur with a `shutter`
+                let shutter = section.positive_or("shutter", if section.entry("iso").is_some() { 1.0 / 125.0 } else { 0.0 })?;
+                let mut c = if physical {
+                    if let Some(entry) = section.entry("fov").or(section.entry("aperture")) {
+                        return Err(section.error(entry.line, &entry.key, "a physical camera takes `focal_length` and `f_stop` instead"))
+                    }
+                    let lens = Lens{
+                        focal_length: section.positive_or("focal_length", Lens::default().focal_length)?,
+                        sensor_width: section.positive_or("sensor", Lens::default().sensor_width)?,
+                        f_stop: section.positive_or("f_stop", Lens::default().f_stop)?,
+                    };
+                    let c = Camera::physical(&lens, &eye, &target, &up);
+                    if section.entry("iso").is_some() || section.entry("shutter").is_some() {
+                        c.with_exposure(lens.exposure(section.positive_or("iso", 100.0)?, shutter))
+                    } else {
+                        c
+                    }
+                } else {
+                    Camera::new(section.parse_or("fov", 90.0)?, &eye, &target, &up, section.parse_or("aperture", 0.0)?)
+                };
+                if section.entry("focus_distance").is_some() {
+                    c = c.with_focus_distance(section.positive_or("focus_distance", 1.0)?);
+                }
+                if section.entry("blades").is_some() {
+                    c = c.with_blades(section.parse("blades")?, section.parse_or("blade_rotation", 0.0)?);
+                }
+                if section.entry("projection").is_some() {
+                    c = c.with_projection(section.parse("projection")?);
+                }
+                // the shutter opens at `time` after the time of the frame
+                c = c.with_shutter(time + section.parse_or("time", 0.0)?, shutter);
+                if let Some(motion) = section.motion("keyframe")? {
+                    c = c.with_motion(motion);
+                }
+                camera = Some(c);
+            },
+            "environment" => {
+                section.expect_keys(&["hdr", "color", "rotation", "intensity"])?;
+                if section.entry("color").is_some() {
+                    ambient_light = Some(AmbientLight::uniform(section.color("color")?));
+                }
+                if section.entry("hdr").is_some() {
+                    let path = section.path("hdr")?;
+                    let light = AmbientLight::load(&path).map_err(|e| section.load_error("hdr", e))?;
+                    ambient_light = Some(light);
+                }
+                if let Some(light) = ambient_light.take() {
+                    ambient_light = Some(light.with_rotation(section.parse_or("rotation", 0.0)?).with_intensity(section.parse_or("intensity", 1.0)?));
+                }
+            },
+            "material" => {
+                let name = section.name.clone().ok_or_else(|| section.error(section.line, "material", "material sections need a name"))?;
+                build_material(section, 1.0, &textures)?;
+                materials.insert(name, section);
+            },
+            "texture" => {
+                let name = section.name.clone().ok_or_else(|| section.error(section.line, "texture", "texture sections need a name"))?;
+                let texture = build_texture(section, &textures)?;
+                textures.insert(name, texture);
+            },
+            "medium" => {
+                let name = section.name.clone().ok_or_else(|| section.error(section.line, "medium", "medium sections need a name"))?;
+                let medium = build_medium(section, &textures)?;
+                media.insert(name, medium);
+            },
+            "fog" => {
+                fog = Some(build_medium(section, &textures)?);
+            },
+            // objects with a medium but without a material only bound the medium
+            "sphere" => {
+                section.expect_keys(&["center", "radius", "material", "medium", "transform", "keyframe"])?;
+                let (center, radius) = (section.vec3("center")?, section.parse("radius")?);
+                let (medium, transform) = (lookup_medium(section, &media)?, section.transform("transform")?);
+                let motion = section.motion("keyframe")?;
+                let sphere = |m| {
+                    let sphere = match &medium {
+                        Some(medium) => Sphere::new(center, radius, m).with_medium(medium.clone()),
+                        None => Sphere::new(center, radius, m)
+                    };
+                    place(section, Box::new(sphere), transform, &motion)
+                };
+                if section.entry("material").is_none() && medium.is_some() {
+                    objs.push(sphere(Box::new(Interface))?);
+                } else {
+                    let material = lookup_material(section, "material", section.str("material")?, &materials)?;
+                    objs.push(object(section, material, &textures, placed_area(4.0 * PI * radius * radius, transform, None), &sphere)?);
+                }
+            },
+            // randomly placed spheres, mostly useful for stress testing
+            "scatter" => {
+                section.expect_keys(&["count", "min", "max", "radius", "materials", "seed"])?;
+                let count: usize = section.parse("count")?;
+                let (min, max) = (section.vec3("min")?, section.vec3("max")?);
+                let radius = match section.floats("radius", 2) {
+                    Ok(r) => (r[0], r[1]),
+                    Err(_) => { let r = section.floats("radius", 1)?; (r[0], r[0]) }
+                };
+                let names: Vec<&str> = section.str("materials")?.split_whitespace().collect();
+                for name in names.iter() {
+                    lookup_material(section, "materials", name, &materials)?;
+                }
+                if names.is_empty() || min.x > max.x || min.y > max.y || min.z > max.z || radius.0 > radius.1 {
+                    return Err(section.error(section.line, "scatter", "needs materials and non-empty ranges"))
+                }
+                let mut rng = StdRng::seed_from_u64(section.parse_or("seed", 0)?);
+                for _ in 0..count {
+                    let center = Vec3{
+                        x: rng.gen_range(min.x..=max.x),
+                        y: rng.gen_range(min.y..=max.y),
+                        z: rng.gen_range(min.z..=max.z),
+                    };
+                    let material = materials[*names.choose(&mut rng).unwrap()];
+                    let radius = rng.gen_range(radius.0..=radius.1);
+                    objs.push(object(section, material, &textures, Some(4.0 * PI * radius * radius), &|m| Ok(Box::new(Sphere::new(center, radius, m))))?);
+                }
+            },
+            "mesh" => {
+                section.expect_keys(&["file", "material", "scale", "offset", "medium", "transform", "keyframe"])?;
+                let path = section.path("file")?;
+                let (scale, offset): (f32, Vec3) = (section.parse_or("scale", 1.0)?, section.vec3_or("offset", Vec3::default())?);
+                let key = (path.clone(), scale.to_bits(), [offset.x.to_bits(), offset.y.to_bits(), offset.z.to_bits()]);
+                if !obj_models.contains_key(&key) {
+                    let models = obj_file::load(&path, scale, offset)
+                        .map_err(|e| section.load_error("file", e))?;
+                    obj_models.insert(key.clone(), models);
+                }
+                let (medium, transform) = (lookup_medium(section, &media)?, section.transform("transform")?);
+                let motion = section.motion("keyframe")?;
+                for model in obj_models[&key].iter() {
+                    let mesh = |m| {
+                        let mesh = match &medium {
+                            Some(medium) => Mesh::shared(model.geometry.clone(), m).with_medium(medium.clone()),
+                            None => Mesh::shared(model.geometry.clone(), m)
+                        };
+                        place(section, Box::new(mesh), transform, &motion)
+                    };
+                    match &model.material {
+                        _ if section.entry("material").is_some() => {
+                            let material = lookup_material(section, "material", section.str("material")?, &materials)?;
+                            objs.push(object(section, material, &textures, placed_area(model.geometry.area(), transform, None), &mesh)?);
+                        },
+                        Some(material) => objs.push(mesh(Box::new(material.clone()))?),
+                        None if medium.is_some() => objs.push(mesh(Box::new(Interface))?),
+                        None => return Err(section.error(section.line, "material",
+                            format!("`{}` in `{}` has no material, set one in [mesh]", model.name, path.display())))
+                    }
+                }
+            },
+            "plane" => {
+                section.expect_keys(&["position", "normal", "size", "material", "transform", "keyframe"])?;
+                let (position, normal, size) = (section.vec3("position")?, section.direction("normal")?, section.floats("size", 2)?);
+                let (transform, motion) = (section.transform("transform")?, section.motion("keyframe")?);
+                let material = lookup_material(section, "material", section.str("material")?, &materials)?;
+                let area = placed_area(size[0] * size[1], transform, Some(&normal));
+                objs.push(object(section, material, &textures, area, &|m| place(section, Box::new(Plane::new(position, normal, (size[0], size[1]), m)), transform, &motion))?);
+            },
+            other => return Err(section.error(section.line, other, "unknown section"))
+        }
+    }
+
+    let camera = camera.ok_or_else(|| ParseError{
+        file: file.to_path_buf(), line: 0, key: "camera".to_string(), reason: "scene has no [camera] section".to_string(), source: None
+    })?;
+    let ambient_light = ambient_light.unwrap_or_else(AmbientLight::new);
+    let mut scene = Scene::builder(camera).objects(objs).ambient_light(ambient_light).build();
+    scene.set_spectral(spectral);
+    scene.set_fog(fog);
+    Ok(SceneFile{scene, samples, resolution})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the entries of the first section at `time`
+    fn animate(source: &str, time: f32) -> Result<Vec<(String, String)>, ParseError> {
+        let mut sections = tokenize(Path::new("test.scene"), source)?;
+        sections[0].animate(time)?;
+        Ok(sections[0].entries.iter().map(|e| (e.key.clone(), e.value.clone())).collect())
+    }
+
+    fn value(source: &str, time: f32, key: &str) -> String {
+        let entries = animate(source, time).unwrap();
+        entries.into_iter().find(|(k, _)| k == key).unwrap().1
+    }
+
+    // line, key and reason of the error in `source`
+    fn error(source: &str) -> (usize, String, String) {
+        let e = parse(Path::new("test.scene"), source).err().unwrap();
+        assert_eq!(e.file, Path::new("test.scene"));
+        (e.line, e.key, e.reason)
+    }
+
+    const CAMERA: &str = "[camera]\neye = 0 -5 0\ntarget = 0 0 0\n";
+
+    // the built-in scene is the one in the scenes directory
+    #[test]
+    fn default_scene() {
+        let (builtin, loaded) = (SceneFile::default(), SceneFile::load(Path::new("scenes/default.scene")).unwrap());
+        assert_eq!((builtin.samples, builtin.resolution), (loaded.samples, loaded.resolution));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            (format!("{}\n[cube]\nsize = 1\n", CAMERA), 5, "cube"),
+            (format!("{}[sphere]\ncenter = 0 0 0\nradius = big\nmaterial = glass\n", CAMERA), 6, "radius"),
+            (format!("{}[sphere]\ncenter = 0 0\nradius = 1\n", CAMERA), 5, "center"),
+            (format!("{}[sphere]\ncenter = 0 0 0\nradius = 1\nmaterial = glass\n", CAMERA), 7, "material"),
+            (format!("{}[sphere]\ncenter = 0 0 0\nradius = 1\n", CAMERA), 4, "material"),
+            (format!("{}[sphere]\ncenter = 0 0 0\ncolour = red\n", CAMERA), 6, "colour"),
+            (format!("{}[material glass]\ntype = glas\n", CAMERA), 5, "type"),
+            (format!("{}[material metal]\ntype = metal\nroughness = 0.2\nf0 = #GG0000\n", CAMERA), 7, "f0"),
+            (format!("{}[sphere]\nradius 1\n", CAMERA), 5, "radius 1"),
+            (format!("{}[sphere\n", CAMERA), 4, ""),
+            ("radius = 1\n".to_string(), 1, "radius"),
+            ("[render]\nsamples = 4\n".to_string(), 0, "camera"),
+        ];
+        for (source, line, key) in cases {
+            let e = error(&source);
+            assert_eq!((e.0, e.1.as_str()), (line, key), "{} for\n{}", e.2, source);
+        }
+
+        let source = format!("{}[sphere]\ncenter = 0 0 0\nradius = big\n", CAMERA);
+        assert_eq!(parse(Path::new("test.scene"), &source).err().unwrap().to_string(), "test.scene:6: `radius`: invalid value `big`");
+    }
+
+    // files that cannot be loaded keep the error of the loader as their source, which is
+    // printed once in the chain
+    #[test]
+    fn load_errors() {
+        use std::error::Error as _;
+        let source = format!("{}[environment]\nhdr = missing.hdr\n", CAMERA);
+        let e = parse(Path::new("scenes/test.scene"), &source).err().unwrap();
+        assert_eq!((e.line, e.key.as_str(), e.reason.as_str()), (5, "hdr", "could not load"));
+        assert!(matches!(e.source.as_deref(), Some(Error::Io{path, ..}) if path == Path::new("scenes/missing.hdr")));
+
+        let e = Error::from(e);
+        assert_eq!(e.to_string(), "scenes/test.scene:5: `hdr`: could not load");
+        let cause = e.source().unwrap();
+        assert_eq!(cause.to_string(), "i/o error on `scenes/missing.hdr`");
+        assert!(cause.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+        assert!(cause.source().unwrap().source().is_none());
+    }
+
+    #[test]
+    fn transform_errors() {
+        let sphere = "[material white]\ntype = diffuse\ndiffuse = 1\n[sphere]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n";
+        for transform in ["scale 0", "spin 90", "translate 1 2", "rotate 0 0 0 90"] {
+            let (line, key, _) = error(&format!("{}{}transform = {}\n", CAMERA, sphere, transform));
+            assert_eq!((line, key.as_str()), (11, "transform"), "{}", transform);
+        }
+    }
+
+    // the exposure of a physical camera and the motion blur use the same shutter
+    #[test]
+    fn one_shutter() {
+        let camera = |keys: &str| {
+            let scene = parse(Path::new("test.scene"), &format!("{}{}", CAMERA, keys)).unwrap().scene;
+            (scene.camera().shutter(), scene.camera().exposure())
+        };
+        let lens = Lens::default();
+        assert_eq!(camera("iso = 400\n"), (1.0 / 125.0, lens.exposure(400.0, 1.0 / 125.0)));
+        assert_eq!(camera("iso = 400\nshutter = 1/30\n"), (1.0 / 30.0, lens.exposure(400.0, 1.0 / 30.0)));
+        assert_eq!(camera("f_stop = 4\n"), (0.0, 0.0));
+        assert_eq!(camera("shutter = 0.5\n"), (0.5, 0.0));
+    }
+
+    // the radiance of an emitter given by its power follows the area of the placed object
+    #[test]
+    fn power_follows_the_placed_area() {
+        use crate::ray::Ray;
+        for (object, area) in [
+            ("[sphere]\ncenter = 0 0 0\nradius = 1\nmaterial = light\ntransform = scale 2\n", 16.0 * PI),
+            ("[plane]\nposition = 0 0 0\nnormal = 0 0 1\nsize = 1 1\nmaterial = light\ntransform = rotate x 30, scale 2 3 1\n", 2.0 * f32::hypot(3.0 * 30f32.to_radians().cos(), 30f32.to_radians().sin())),
+            ("[scatter]\ncount = 1\nmin = 0 0 0\nmax = 0 0 0\nradius = 2\nmaterials = light\n", 16.0 * PI),
+        ] {
+            let source = format!("[camera]\neye = 0 -5 0\ntarget = 0 0 0\n[material light]\ntype = emissive\npower = 10\n{}", object);
+            let scene = parse(Path::new("test.scene"), &source).unwrap().scene;
+            let ray = Ray::new(Vec3{x: 0.0, y: 0.0, z: 10.0}, Vec3n::new(0.0, 0.0, -1.0));
+            let (light, hit) = scene.first_intersect(&ray).unwrap();
+            assert!((light.area() - area).abs() < 1e-3 * area, "{} != {}", light.area(), area);
+            let power = light.emitted(&hit, &ray).r * PI * area;
+            assert!((power - 10.0).abs() < 1e-2, "{} != 10", power);
+        }
+
+        let source = "[camera]\neye = 0 -5 0\ntarget = 0 0 0\n[material light]\ntype = emissive\npower = 10\n[sphere]\ncenter = 0 0 0\nradius = 1\nmaterial = light\ntransform = scale 1 2 1\n";
+        let e = parse(Path::new("test.scene"), source).err().unwrap();
+        assert_eq!((e.line, e.key.as_str()), (11, "transform"));
+    }
+
+    #[test]
+    fn keyframes() {
+        let source = "[sphere]\nradius @ 2 = 3\nradius @ 0 = 1\nmaterial = glass\n";
+        assert_eq!(value(source, -1.0, "radius"), "1");
+        assert_eq!(value(source, 1.0, "radius"), "2");
+        assert_eq!(value(source, 5.0, "radius"), "3");
+        assert_eq!(value(source, 1.0, "material"), "glass");
+    }
+
+    // the text and commas of a value are kept, only its numbers change
+    #[test]
+    fn keyframes_with_words_and_commas() {
+        let source = "[sphere]\ntransform @ 0 = translate 0 0 0, scale 1\ntransform @ 2 = translate 2 0 4, scale 3\n";
+        assert_eq!(value(source, 1.0, "transform"), "translate 1 0 2, scale 2");
+    }
+
+    #[test]
+    fn whole_numbers_stay_whole() {
+        let source = "[render]\nsamples @ 0 = 1\nsamples @ 1 = 4\n";
+        assert_eq!(value(source, 0.5, "samples"), "3");
+        let scene = parse_at(Path::new("test.scene"), &format!("{}[camera]\neye = 0 -5 0\ntarget = 0 0 0\n", source), 0.5).unwrap();
+        assert_eq!(scene.samples, Some(3));
+    }
+
+    #[test]
+    fn keyframe_errors() {
+        let error = |source: &str| {
+            let e = animate(source, 0.0).unwrap_err();
+            (e.line, e.key, e.reason)
+        };
+        assert_eq!(error("[sphere]\nradius @ 0 = 1\nradius = 2\n"), (3, "radius".to_string(), "has keyframes as well as a value".to_string()));
+        assert_eq!(error("[sphere]\ntransform @ 0 = scale 1\ntransform @ 1 = translate 1 1 1\n").0, 3);
+        assert_eq!(error("[sphere]\ntransform @ 0 = scale 1, scale 1\ntransform @ 1 = scale 1 scale 1\n").1, "transform @ 1");
+        assert_eq!(error("[material light]\ntype = light\nradiance @ 0 = 1\nradiance @ 1 = 1 2 3\n").2, "keyframes may only differ in their numbers");
+        assert_eq!(error("[sphere]\nradius @ soon = 1\n").0, 2);
+        assert_eq!(error("[sphere]\nradius @ 0 smooth = 1\n").1, "radius @ 0 smooth");
+        assert_eq!(error("[sphere]\nradius @ 0 smooth = 1\n").2, INVALID_KEYFRAME_TIME);
+    }
+
+    // object motion reads its keyframe times like the `@` tracks
+    #[test]
+    fn motion_keyframes() {
+        let motion = |value: &str| {
+            let source = format!("[sphere]\nradius = 1\nkeyframe = 0: translate 0 0 0\nkeyframe = {}\n", value);
+            let sections = tokenize(Path::new("test.scene"), &source).unwrap();
+            sections[0].motion("keyframe").map(|m| m.unwrap())
+        };
+        let m = motion("2 bezier: translate 2 0 0").unwrap();
+        assert!((m.keyframe(1.0).translation.x - 1.0).abs() < 1e-5);
+        assert_eq!(motion("2 smooth: translate 2 0 0").unwrap_err().reason, INVALID_KEYFRAME_TIME);
+        assert_eq!(motion("soon: translate 2 0 0").unwrap_err().reason, INVALID_KEYFRAME_TIME);
+        assert_eq!(motion("2 translate 2 0 0").unwrap_err().line, 4);
+    }
+}
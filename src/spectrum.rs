@@ -0,0 +1,299 @@
+use crate::color::Color;
+
+use std::sync::OnceLock;
+
+// visible range the wavelengths are sampled from, in nm
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+
+// wavelength of the sodium d line, where glasses are usually specified
+pub const D_LINE: f32 = 587.6;
+
+// the wavelengths (in nm) a path carries in spectral mode, the channels of the colors along
+// the path hold the values at these wavelengths instead of red, green and blue. The first
+// one is the hero wavelength that decides wavelength dependent directions, the others are
+// spread evenly over the visible range (Wilkie et al. 2014).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: [f32; 3],
+    // the other wavelengths were dropped where they split from the hero one
+    pub hero_only: bool,
+}
+
+impl Wavelengths {
+    pub fn sample(u: f32) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let lambda = [0.0, 1.0, 2.0].map(|i| {
+            let lambda = hero + i * range / 3.0;
+            if lambda >= LAMBDA_MAX { lambda - range } else { lambda }
+        });
+        Wavelengths{lambda, hero_only: false}
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    // drops the other wavelengths at a wavelength dependent direction, returns the remaining
+    // wavelengths and the path weight that lets the hero wavelength take over their share
+    pub fn disperse(&self) -> (Wavelengths, Color) {
+        if self.hero_only { return (*self, Color::new(1.0)) }
+        (Wavelengths{hero_only: true, ..*self}, Color{r: 3.0, g: 0.0, b: 0.0})
+    }
+
+    // the values of `f` at the wavelengths
+    pub fn map(&self, f: impl Fn(f32) -> f32) -> Color {
+        let [r, g, b] = self.lambda.map(f);
+        Color{r, g, b}
+    }
+
+    // a smooth spectrum for an rgb color, sampled at the wavelengths. The spectrum is a blend
+    // of three basis functions that sum to one, so grey stays constant and reflectances stay
+    // within [0, 1].
+    pub fn uplift(&self, c: &Color) -> Color {
+        self.map(|lambda| {
+            let [r, g, b] = basis(lambda);
+            c.r * r + c.g * g + c.b * b
+        })
+    }
+
+    // linear sRGB of the values at the wavelengths, an unbiased estimate of the color of the
+    // spectrum they were sampled from. Uplifted colors convert back to themselves on average.
+    pub fn to_rgb(&self, values: &Color) -> Color {
+        let [r, g, b] = self.lambda;
+        let xyz = (cie_xyz(r) * values.r + cie_xyz(g) * values.g + cie_xyz(b) * values.b) * ((LAMBDA_MAX - LAMBDA_MIN) / 3.0);
+        from_xyz(&xyz)
+    }
+}
+
+fn sigmoid(lambda: f32, edge: f32) -> f32 {
+    1.0 / (1.0 + f32::exp(-(lambda - edge) / 10.0))
+}
+
+// weights of red, green and blue at a wavelength
+fn basis(lambda: f32) -> [f32; 3] {
+    let red = sigmoid(lambda, 590.0);
+    let blue = 1.0 - sigmoid(lambda, 490.0);
+    [red, 1.0 - red - blue, blue]
+}
+
+fn lobe(lambda: f32, mean: f32, below: f32, above: f32) -> f32 {
+    let t = (lambda - mean) / if lambda < mean { below } else { above };
+    f32::exp(-0.5 * t * t)
+}
+
+// CIE 1931 2° color matching functions, the multi lobe fit of Wyman et al. 2013, returned
+// as a color with x, y and z in r, g and b
+pub fn cie_xyz(lambda: f32) -> Color {
+    Color {
+        r: 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        g: 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        b: 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    }
+}
+
+type Matrix = [[f32; 3]; 3];
+
+const XYZ_TO_SRGB: Matrix = [
+    [3.2406, -1.5372, -0.4986],
+    [-0.9689, 1.8758, 0.0415],
+    [0.0557, -0.2040, 1.0570],
+];
+
+fn mul(m: &Matrix, c: &Color) -> Color {
+    let row = |r: &[f32; 3]| r[0] * c.r + r[1] * c.g + r[2] * c.b;
+    Color{r: row(&m[0]), g: row(&m[1]), b: row(&m[2])}
+}
+
+fn invert(m: &Matrix) -> Matrix {
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1, c0, c1) = ((r + 1) % 3, (r + 2) % 3, (c + 1) % 3, (c + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f32 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+    let mut inverse = [[0.0; 3]; 3];
+    for (r, row) in inverse.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = cofactor(c, r) / det;
+        }
+    }
+    inverse
+}
+
+// integral of `f` times the color matching functions over the visible range
+fn integrate_xyz(f: impl Fn(f32) -> f32) -> Color {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    (0..steps).map(|i| LAMBDA_MIN + i as f32 + 0.5).fold(Color::default(), |sum, lambda| sum + cie_xyz(lambda) * f(lambda))
+}
+
+// xyz to the rgb space of the uplifted colors: sRGB, corrected so that the spectrum of every
+// basis color converts back to that color
+fn from_xyz(xyz: &Color) -> Color {
+    static CORRECTION: OnceLock<Matrix> = OnceLock::new();
+    let correction = CORRECTION.get_or_init(|| {
+        let columns = [0, 1, 2].map(|i| mul(&XYZ_TO_SRGB, &integrate_xyz(|lambda| basis(lambda)[i])));
+        invert(&[
+            [columns[0].r, columns[1].r, columns[2].r],
+            [columns[0].g, columns[1].g, columns[2].g],
+            [columns[0].b, columns[1].b, columns[2].b],
+        ])
+    });
+    mul(correction, &mul(&XYZ_TO_SRGB, xyz))
+}
+
+// rgb color of a reflectance spectrum
+pub fn reflectance_to_rgb(f: impl Fn(f32) -> f32) -> Color {
+    from_xyz(&integrate_xyz(f))
+}
+
+// wavelength dependent index of refraction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ior {
+    Constant(f32),
+    // n = a + b / λ², with λ in μm
+    Cauchy{a: f32, b: f32},
+    // n² = 1 + Σ b λ² / (λ² - c), with λ in μm
+    Sellmeier{b: [f32; 3], c: [f32; 3]},
+}
+
+impl Ior {
+    // borosilicate crown glass, the common optical glass
+    pub fn bk7() -> Ior {
+        Ior::Sellmeier{b: [1.039_612, 0.231_792_34, 1.010_469_4], c: [0.006_000_699, 0.020_017_914, 103.560_65]}
+    }
+
+    pub fn fused_silica() -> Ior {
+        Ior::Sellmeier{b: [0.696_166_3, 0.407_942_6, 0.897_479_4], c: [0.004_679_148, 0.013_512_063, 97.934_0]}
+    }
+
+    // dense flint glass with strong dispersion
+    pub fn sf11() -> Ior {
+        Ior::Sellmeier{b: [1.737_597, 0.313_747_35, 1.898_781], c: [0.013_188_707, 0.062_306_814, 155.236_3]}
+    }
+
+    pub fn diamond() -> Ior {
+        Ior::Sellmeier{b: [0.3306, 4.3356, 0.0], c: [0.030_625, 0.011_236, 0.0]}
+    }
+
+    pub fn at(&self, lambda: f32) -> f32 {
+        let micrometers = lambda / 1000.0;
+        let l2 = micrometers * micrometers;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy{a, b} => a + b / l2,
+            Ior::Sellmeier{b, c} => f32::sqrt(1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()),
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+// complex index of refraction n + ik of a metal, approximate values read off published
+// measurements (Johnson and Christy 1972, Rakić 1995) every 50 nm
+pub struct Conductor {
+    // wavelength in nm, n, k
+    samples: &'static [(f32, f32, f32)],
+}
+
+impl Conductor {
+    pub const GOLD: Conductor = Conductor{samples: &[
+        (400.0, 1.66, 1.96), (450.0, 1.50, 1.88), (500.0, 0.97, 1.87), (550.0, 0.43, 2.46), (600.0, 0.25, 2.98),
+        (650.0, 0.17, 3.45), (700.0, 0.16, 3.95), (750.0, 0.16, 4.40), (800.0, 0.16, 4.85),
+    ]};
+    pub const SILVER: Conductor = Conductor{samples: &[
+        (400.0, 0.05, 2.07), (450.0, 0.04, 2.65), (500.0, 0.05, 3.13), (550.0, 0.06, 3.59), (600.0, 0.06, 4.01),
+        (650.0, 0.05, 4.44), (700.0, 0.04, 4.83), (750.0, 0.03, 5.24), (800.0, 0.03, 5.60),
+    ]};
+    pub const COPPER: Conductor = Conductor{samples: &[
+        (400.0, 1.18, 2.21), (450.0, 1.17, 2.43), (500.0, 1.12, 2.60), (550.0, 0.95, 2.58), (600.0, 0.27, 3.41),
+        (650.0, 0.21, 3.67), (700.0, 0.21, 4.20), (750.0, 0.22, 4.60), (800.0, 0.26, 5.05),
+    ]};
+    pub const ALUMINIUM: Conductor = Conductor{samples: &[
+        (400.0, 0.49, 4.86), (450.0, 0.62, 5.47), (500.0, 0.77, 6.08), (550.0, 0.96, 6.69), (600.0, 1.20, 7.26),
+        (650.0, 1.47, 7.79), (700.0, 1.83, 8.31), (750.0, 2.40, 8.62), (800.0, 2.80, 8.45),
+    ]};
+
+    // linearly interpolated, clamped to the ends of the table
+    pub fn nk(&self, lambda: f32) -> (f32, f32) {
+        let samples = self.samples;
+        let i = samples.partition_point(|s| s.0 < lambda);
+        if i == 0 { return (samples[0].1, samples[0].2) }
+        if i == samples.len() { return (samples[i - 1].1, samples[i - 1].2) }
+        let (a, b) = (samples[i - 1], samples[i]);
+        let t = (lambda - a.0) / (b.0 - a.0);
+        (a.1 + t * (b.1 - a.1), a.2 + t * (b.2 - a.2))
+    }
+
+    // reflectance at normal incidence
+    pub fn f0(&self, lambda: f32) -> f32 {
+        let (n, k) = self.nk(lambda);
+        ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k)
+    }
+
+    // reflectance of unpolarized light arriving from air at `cos_theta` to the normal
+    pub fn fresnel(&self, lambda: f32, cos_theta: f32) -> f32 {
+        let (n, k) = self.nk(lambda);
+        let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let t0 = n * n - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4.0 * n * n * k * k).sqrt();
+        let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+        let t1 = a2b2 + cos2;
+        let t2 = 2.0 * a * cos2.sqrt();
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    }
+
+    pub fn rgb_f0(&self) -> Color {
+        reflectance_to_rgb(|lambda| self.f0(lambda))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // converting the uplifted spectrum of a color back gives the color on average
+    #[test]
+    fn uplift_round_trip() {
+        let color = Color{r: 0.8, g: 0.3, b: 0.1};
+        let n = 10000;
+        let sum = (0..n).map(|i| {
+            let wavelengths = Wavelengths::sample((i as f32 + 0.5) / n as f32);
+            wavelengths.to_rgb(&wavelengths.uplift(&color))
+        }).fold(Color::default(), |a, b| a + b) / n as f32;
+        for (a, b) in [(sum.r, color.r), (sum.g, color.g), (sum.b, color.b)] {
+            assert!((a - b).abs() < 1e-3, "{:?} != {:?}", sum, color);
+        }
+    }
+
+    // the exact fresnel term starts at f0, reaches 1 at grazing angles and differs from
+    // schlick's approximation in between
+    #[test]
+    fn conductor_fresnel() {
+        for conductor in [&Conductor::GOLD, &Conductor::ALUMINIUM] {
+            for lambda in [450.0, 550.0, 650.0] {
+                let f0 = conductor.f0(lambda);
+                assert!((conductor.fresnel(lambda, 1.0) - f0).abs() < 1e-5);
+                assert!((conductor.fresnel(lambda, 0.0) - 1.0).abs() < 1e-5);
+            }
+        }
+        // aluminium dips below f0 before rising towards grazing angles
+        let f0 = Conductor::ALUMINIUM.f0(800.0);
+        assert!(Conductor::ALUMINIUM.fresnel(800.0, 0.3) < f0);
+    }
+
+    #[test]
+    fn sellmeier_glasses() {
+        assert!((Ior::bk7().at(D_LINE) - 1.5168).abs() < 1e-3);
+        assert!((Ior::sf11().at(D_LINE) - 1.7847).abs() < 1e-3);
+        // blue light is refracted more strongly
+        assert!(Ior::bk7().at(450.0) > Ior::bk7().at(650.0));
+    }
+}
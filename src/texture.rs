@@ -0,0 +1,187 @@
+use crate::color::Color;
+use crate::image::{Image, Wrap};
+use crate::ray::Intersection;
+use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+// a material parameter that varies over the surface
+pub trait Texture {
+    fn value(&self, intersection: &Intersection) -> Color;
+    // whether the value only depends on the position, so it can be used inside media
+    fn is_solid(&self) -> bool { false }
+}
+
+impl Texture for Color {
+    fn value(&self, _intersection: &Intersection) -> Color {
+        *self
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+}
+
+// an image mapped onto the uv coordinates, v = 0 is the bottom row
+pub struct ImageTexture {
+    image: Arc<Image>,
+    wrap: Wrap,
+    // repetitions of the image per unit of uv
+    scale: f32,
+}
+
+impl ImageTexture {
+    pub fn new(image: Arc<Image>, wrap: Wrap, scale: f32) -> ImageTexture {
+        ImageTexture{image, wrap, scale}
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, intersection: &Intersection) -> Color {
+        let (u, v) = intersection.uv;
+        self.image.bilinear(u * self.scale, 1.0 - v * self.scale, self.wrap, self.wrap)
+    }
+}
+
+// alternates between two textures in a `scale` x `scale` grid over the uv coordinates
+pub struct Checker {
+    even: Arc<dyn Texture + Send + Sync>,
+    odd: Arc<dyn Texture + Send + Sync>,
+    scale: f32,
+}
+
+impl Checker {
+    pub fn new(even: Arc<dyn Texture + Send + Sync>, odd: Arc<dyn Texture + Send + Sync>, scale: f32) -> Checker {
+        Checker{even, odd, scale}
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, intersection: &Intersection) -> Color {
+        let (u, v) = intersection.uv;
+        let cell = (u * self.scale).floor() as i64 + (v * self.scale).floor() as i64;
+        if cell.rem_euclid(2) == 0 { self.even.value(intersection) } else { self.odd.value(intersection) }
+    }
+}
+
+// perlin noise in world space blending between two colors, octaves above one add
+// finer detail (fractal brownian motion)
+pub struct Noise {
+    low: Color,
+    high: Color,
+    // frequency per world unit
+    scale: f32,
+    octaves: u32,
+    permutation: Vec<usize>,
+}
+
+const GRADIENTS: [Vec3; 12] = [
+    Vec3{x: 1.0, y: 1.0, z: 0.0}, Vec3{x: -1.0, y: 1.0, z: 0.0}, Vec3{x: 1.0, y: -1.0, z: 0.0}, Vec3{x: -1.0, y: -1.0, z: 0.0},
+    Vec3{x: 1.0, y: 0.0, z: 1.0}, Vec3{x: -1.0, y: 0.0, z: 1.0}, Vec3{x: 1.0, y: 0.0, z: -1.0}, Vec3{x: -1.0, y: 0.0, z: -1.0},
+    Vec3{x: 0.0, y: 1.0, z: 1.0}, Vec3{x: 0.0, y: -1.0, z: 1.0}, Vec3{x: 0.0, y: 1.0, z: -1.0}, Vec3{x: 0.0, y: -1.0, z: -1.0},
+];
+
+impl Noise {
+    pub fn new(low: Color, high: Color, scale: f32, octaves: u32, seed: u64) -> Noise {
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(&mut StdRng::seed_from_u64(seed));
+        Noise{low, high, scale, octaves: octaves.max(1), permutation}
+    }
+
+    // gradient noise in about [-1, 1]
+    fn perlin(&self, p: &Vec3) -> f32 {
+        let cell = [p.x.floor(), p.y.floor(), p.z.floor()];
+        let f = [p.x - cell[0], p.y - cell[1], p.z - cell[2]];
+        let cell = cell.map(|c| (c as i64).rem_euclid(256) as usize);
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let hash = |x: usize, y: usize, z: usize| {
+            self.permutation[(self.permutation[(self.permutation[x % 256] + y) % 256] + z) % 256]
+        };
+
+        let mut result = 0.0;
+        for corner in 0..8 {
+            let (dx, dy, dz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let gradient = GRADIENTS[hash(cell[0] + dx, cell[1] + dy, cell[2] + dz) % 12];
+            let offset = Vec3{x: f[0] - dx as f32, y: f[1] - dy as f32, z: f[2] - dz as f32};
+            let weight = |d: usize, t: f32| if d == 1 { fade(t) } else { 1.0 - fade(t) };
+            result += weight(dx, f[0]) * weight(dy, f[1]) * weight(dz, f[2]) * (gradient * offset);
+        }
+        result
+    }
+}
+
+impl Texture for Noise {
+    fn value(&self, intersection: &Intersection) -> Color {
+        let (mut sum, mut amplitude, mut frequency, mut total) = (0.0, 1.0, self.scale, 0.0);
+        for _ in 0..self.octaves {
+            sum += amplitude * self.perlin(&(intersection.pos * frequency));
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        let t = (0.5 + 0.5 * sum / total).clamp(0.0, 1.0);
+        self.low * (1.0 - t) + self.high * t
+    }
+    fn is_solid(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Resolution;
+    use crate::vec3::Vec3n;
+
+    fn at(pos: Vec3, uv: (f32, f32)) -> Intersection {
+        Intersection::new(pos, Vec3n::new(0.0, 0.0, 1.0), &Vec3{x: 1.0, y: 0.0, z: 0.0}, uv, 1.0)
+    }
+
+    #[test]
+    fn checker_parity() {
+        let checker = Checker::new(Arc::new(Color::new(1.0)), Arc::new(Color::new(0.0)), 2.0);
+        for (uv, even) in [((0.1, 0.1), true), ((0.6, 0.1), false), ((0.1, 0.6), false), ((0.6, 0.6), true),
+                           ((-0.1, 0.1), false), ((-0.1, -0.1), true), ((1.1, 0.1), true), ((1.6, 2.1), false)] {
+            assert_eq!(checker.value(&at(Vec3::default(), uv)).r == 1.0, even, "{:?}", uv);
+        }
+        assert!(!checker.is_solid());
+    }
+
+    // v = 0 is the bottom row of the image
+    #[test]
+    fn image_orientation() {
+        let mut image = Image::new(Resolution::new(1, 2));
+        image.get_data_mut()[0] = Color::new(1.0);
+        let texture = ImageTexture::new(Arc::new(image), Wrap::Clamp, 1.0);
+        assert_eq!(texture.value(&at(Vec3::default(), (0.5, 0.75))).r, 1.0);
+        assert_eq!(texture.value(&at(Vec3::default(), (0.5, 0.25))).r, 0.0);
+        // twice per unit of uv
+        let texture = ImageTexture{scale: 2.0, wrap: Wrap::Repeat, ..texture};
+        assert_eq!(texture.value(&at(Vec3::default(), (0.5, 0.375))).r, 1.0);
+    }
+
+    // the same seed gives the same solid noise between the two colors, other seeds differ
+    #[test]
+    fn noise() {
+        let (low, high) = (Color::new(0.2), Color::new(0.8));
+        let noise = Noise::new(low, high, 1.5, 3, 4);
+        let mut rng = StdRng::seed_from_u64(1);
+        let points: Vec<Vec3> = (0..1000).map(|_| Vec3{x: rng.gen_range(-20.0..20.0), y: rng.gen_range(-20.0..20.0), z: rng.gen_range(-20.0..20.0)}).collect();
+        let values: Vec<f32> = points.iter().map(|&p| noise.value(&at(p, (0.0, 0.0))).r).collect();
+        assert!(values.iter().all(|v| (0.2..=0.8).contains(v)));
+        assert!(values.iter().any(|&v| v < 0.4) && values.iter().any(|&v| v > 0.6));
+
+        // only the position matters and nearby points have nearby values
+        let p = points[0];
+        assert_eq!(noise.value(&at(p, (0.3, 0.7))).r, values[0]);
+        assert!((noise.value(&at(p + Vec3::from(1e-3), (0.0, 0.0))).r - values[0]).abs() < 0.01);
+        assert!(noise.is_solid());
+
+        let same = Noise::new(low, high, 1.5, 3, 4);
+        let other = Noise::new(low, high, 1.5, 3, 5);
+        assert!(points.iter().zip(values.iter()).all(|(&p, &v)| same.value(&at(p, (0.0, 0.0))).r == v));
+        assert!(points.iter().zip(values.iter()).any(|(&p, &v)| other.value(&at(p, (0.0, 0.0))).r != v));
+    }
+}
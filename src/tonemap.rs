@@ -0,0 +1,208 @@
+use crate::color::Color;
+use crate::image::Image;
+
+use std::str::FromStr;
+
+// key value used by the auto exposure, maps the average luminance to middle grey
+const AUTO_EXPOSURE_KEY: f32 = 0.18;
+// luminance that maps to white with `reinhard-extended` if none is given
+const DEFAULT_WHITE: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Tonemapper {
+    Clamp,
+    Reinhard,
+    ReinhardExtended{white: f32},
+    Aces,
+    Hejl,
+}
+
+#[derive(Debug, Clone)]
+pub struct TonemapperParseError(String);
+
+impl std::fmt::Display for TonemapperParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown tonemapper `{}`, use clamp, reinhard, reinhard-extended[:<white>], aces or hejl", self.0)
+    }
+}
+
+impl FromStr for Tonemapper {
+    type Err = TonemapperParseError;
+
+    // `reinhard-extended:8` maps a luminance of 8 to white
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(white) = s.strip_prefix("reinhard-extended:") {
+            return match white.parse::<f32>() {
+                Ok(white) if white.is_finite() && white > 0.0 => Ok(Tonemapper::ReinhardExtended{white}),
+                _ => Err(TonemapperParseError(s.to_string()))
+            }
+        }
+        match s {
+            "clamp" => Ok(Tonemapper::Clamp),
+            "reinhard" => Ok(Tonemapper::Reinhard),
+            "reinhard-extended" => Ok(Tonemapper::ReinhardExtended{white: DEFAULT_WHITE}),
+            "aces" => Ok(Tonemapper::Aces),
+            "hejl" => Ok(Tonemapper::Hejl),
+            _ => Err(TonemapperParseError(s.to_string()))
+        }
+    }
+}
+
+fn scale_luminance(c: &Color, f: impl Fn(f32) -> f32) -> Color {
+    let l = c.luminance();
+    if l <= 0.0 { Color::default() } else { c * (f(l) / l) }
+}
+
+fn srgb(x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+// ACES fit by Stephen Hill, input and output matrices around the RRT and ODT curve
+fn aces(c: &Color) -> Color {
+    let r = 0.59719 * c.r + 0.35458 * c.g + 0.04823 * c.b;
+    let g = 0.07600 * c.r + 0.90834 * c.g + 0.01566 * c.b;
+    let b = 0.02840 * c.r + 0.13383 * c.g + 0.83777 * c.b;
+    let fit = |v: f32| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081);
+    let (r, g, b) = (fit(r), fit(g), fit(b));
+    Color{
+        r: 1.60475 * r - 0.53108 * g - 0.07367 * b,
+        g: -0.10208 * r + 1.10813 * g - 0.00605 * b,
+        b: -0.00327 * r - 0.07276 * g + 1.07602 * b,
+    }
+}
+
+impl Tonemapper {
+    // maps linear radiance to display encoded values in [0, 1]
+    pub fn map(&self, c: &Color) -> Color {
+        let c = Color{r: c.r.max(0.0), g: c.g.max(0.0), b: c.b.max(0.0)};
+        let linear = match self {
+            Tonemapper::Clamp => c,
+            Tonemapper::Reinhard => scale_luminance(&c, |l| l / (1.0 + l)),
+            Tonemapper::ReinhardExtended{white} => scale_luminance(&c, |l| l * (1.0 + l / (white * white)) / (1.0 + l)),
+            Tonemapper::Aces => aces(&c),
+            Tonemapper::Hejl => {
+                // filmic tonemap formula by Jim Hejl and Richard Burgess-Dawson, includes the gamma
+                let x = c - 0.004;
+                let x = Color{r: x.r.max(0.0), g: x.g.max(0.0), b: x.b.max(0.0)};
+                let color = (x*(6.2*x+0.5))/(x*(6.2*x+1.7)+0.06);
+                return Color{r: color.r.clamp(0.0, 1.0), g: color.g.clamp(0.0, 1.0), b: color.b.clamp(0.0, 1.0)}
+            }
+        };
+        Color{r: srgb(linear.r), g: srgb(linear.g), b: srgb(linear.b)}
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tonemap {
+    pub tonemapper: Tonemapper,
+    // exposure compensation in stops
+    pub exposure: f32,
+    pub auto_exposure: bool,
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Tonemap{tonemapper: Tonemapper::Hejl, exposure: 0.0, auto_exposure: false}
+    }
+}
+
+impl Tonemap {
+    // linear factor applied to the radiance before tonemapping
+    pub fn scale(&self, image: &Image) -> f32 {
+        let scale = f32::powf(2.0, self.exposure);
+        if self.auto_exposure {
+            scale * AUTO_EXPOSURE_KEY / Tonemap::log_average_luminance(image)
+        } else {
+            scale
+        }
+    }
+
+    fn log_average_luminance(image: &Image) -> f32 {
+        let data = image.get_data();
+        let (sum, count) = data.iter().map(Color::luminance).filter(|l| l.is_finite())
+            .fold((0.0f64, 0usize), |(sum, count), l| (sum + f64::ln(1e-4 + l.max(0.0) as f64), count + 1));
+        if count == 0 { 1.0 } else { f64::exp(sum / count as f64) as f32 }
+    }
+
+    pub fn apply(&self, image: &Image) -> Vec<u8> {
+        let scale = self.scale(image);
+        image.get_data().iter().flat_map(|c| {
+            let c = self.tonemapper.map(&(c.fix() * scale));
+            [c.r, c.g, c.b].map(|v| (v * 255.0).round() as u8)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Resolution;
+
+    fn grey(tonemapper: Tonemapper, x: f32) -> f32 {
+        let c = tonemapper.map(&Color::new(x));
+        assert!((c.r - c.g).abs() < 1e-5 && (c.g - c.b).abs() < 1e-5, "{:?}", c);
+        c.r
+    }
+
+    #[test]
+    fn parse() {
+        assert!(matches!("reinhard-extended".parse(), Ok(Tonemapper::ReinhardExtended{white}) if white == DEFAULT_WHITE));
+        assert!(matches!("reinhard-extended:8".parse(), Ok(Tonemapper::ReinhardExtended{white}) if white == 8.0));
+        for invalid in ["reinhard-extended:", "reinhard-extended:0", "reinhard-extended:-2", "reinhard-extended:inf", "reinhard:2", "filmic"] {
+            assert!(invalid.parse::<Tonemapper>().is_err(), "{}", invalid);
+        }
+    }
+
+    // the linear part ends where both pieces meet at 0.04045
+    #[test]
+    fn srgb_encoding() {
+        assert_eq!(srgb(0.0), 0.0);
+        assert_eq!(srgb(-1.0), 0.0);
+        assert!((srgb(1.0) - 1.0).abs() < 1e-6);
+        assert_eq!(srgb(2.0), srgb(1.0));
+        assert!((srgb(0.0031308) - 0.04045).abs() < 1e-5);
+        assert!((srgb(0.0031309) - 0.04045).abs() < 1e-5);
+        assert!((srgb(0.5) - 0.735357).abs() < 1e-5);
+    }
+
+    #[test]
+    fn operator_curves() {
+        for (tonemapper, x, expected) in [
+            (Tonemapper::Clamp, 0.5, srgb(0.5)),
+            (Tonemapper::Clamp, 3.0, 1.0),
+            // x / (1 + x)
+            (Tonemapper::Reinhard, 1.0, srgb(0.5)),
+            (Tonemapper::Reinhard, 3.0, srgb(0.75)),
+            // x (1 + x / white²) / (1 + x) reaches one at the white point
+            (Tonemapper::ReinhardExtended{white: 4.0}, 1.0, srgb(0.53125)),
+            (Tonemapper::ReinhardExtended{white: 4.0}, 4.0, 1.0),
+            (Tonemapper::Aces, 0.18, srgb(0.105591)),
+            (Tonemapper::Aces, 1.0, srgb(0.619115)),
+            (Tonemapper::Aces, 1000.0, 1.0),
+            // already display encoded, black below its toe
+            (Tonemapper::Hejl, 0.004, 0.0),
+            (Tonemapper::Hejl, 0.5, 0.730204),
+            (Tonemapper::Hejl, 1000.0, 1.0),
+        ] {
+            let value = grey(tonemapper, x);
+            assert!((value - expected).abs() < 1e-3, "{:?} of {}: {} != {}", tonemapper, x, value, expected);
+        }
+        // black stays black
+        for tonemapper in [Tonemapper::Clamp, Tonemapper::Reinhard, Tonemapper::ReinhardExtended{white: 4.0}, Tonemapper::Aces, Tonemapper::Hejl] {
+            assert_eq!(grey(tonemapper, 0.0), 0.0, "{:?}", tonemapper);
+        }
+    }
+
+    #[test]
+    fn auto_exposure() {
+        let mut image = Image::new(Resolution::new(4, 2));
+        image.get_data_mut().iter_mut().for_each(|c| *c = Color::new(5.0));
+        let tonemap = Tonemap{tonemapper: Tonemapper::Clamp, exposure: 0.0, auto_exposure: true};
+        assert!((tonemap.scale(&image) * 5.0 - AUTO_EXPOSURE_KEY).abs() < 1e-4);
+        // exposure compensation on top of it
+        let brighter = Tonemap{exposure: 1.0, ..tonemap};
+        assert!((brighter.scale(&image) * 5.0 - 2.0 * AUTO_EXPOSURE_KEY).abs() < 1e-4);
+        assert_eq!(tonemap.apply(&image)[0], (srgb(AUTO_EXPOSURE_KEY) * 255.0).round() as u8);
+    }
+}
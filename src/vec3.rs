@@ -1,6 +1,8 @@
 use std::ops::Neg;
 use std::ops;
 
+use crate::random::sample_rng;
+
 use rand::prelude::*;
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -40,7 +42,7 @@ impl Vec3 {
     }
 
     pub fn random(min: f32, max: f32) -> Self {
-        let mut rng = thread_rng();
+        let mut rng = sample_rng();
         Vec3 {
             x: rng.gen_range(min..max),
             y: rng.gen_range(min..max),
@@ -78,17 +80,25 @@ impl Vec3n {
         }
     }
 
-    pub fn random_sphere() -> Self {
-        loop {
-            let p = Vec3::random(-1.0, 1.0);
-            if p.len_squared() >= 1.0 {continue} else {return Vec3n::from(p)}
-        }
+    // unit tangent and bitangent perpendicular to the normal, the tangent follows `hint`
+    // unless it is parallel to the normal
+    pub fn tangent_frame(&self, hint: &Vec3) -> (Self, Self) {
+        let n = Vec3::from(self);
+        let tangent = hint - (n * hint) * n;
+        let tangent = if tangent.len_squared() > 1e-12 {
+            tangent
+        } else if n.x.abs() < 0.9 {
+            Vec3{x: 1.0, y: 0.0, z: 0.0} - n.x * n
+        } else {
+            Vec3{x: 0.0, y: 1.0, z: 0.0} - n.y * n
+        };
+        let tangent = tangent.norm();
+        (tangent, self.cross(&tangent))
     }
 
-    pub fn random_disc() -> Self {
+    pub fn random_sphere() -> Self {
         loop {
-            let mut p = Vec3::random(-1.0, 1.0);
-            p.z = 0.;
+            let p = Vec3::random(-1.0, 1.0);
             if p.len_squared() >= 1.0 {continue} else {return Vec3n::from(p)}
         }
     }
@@ -220,3 +230,27 @@ impl_op_ex!(/ |a: &Vec3, b: f32| -> Vec3 {
         z: a.z / b,
     }
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an orthonormal right handed frame whose tangent follows the hint
+    #[test]
+    fn tangent_frame() {
+        let normal = Vec3n::new(0.3, -0.5, 0.8);
+        for hint in [Vec3{x: 1.0, y: 0.0, z: 0.0}, Vec3{x: 0.0, y: 2.0, z: 1.0}, Vec3::from(normal), 3.0 * Vec3::from(normal)] {
+            let (tangent, bitangent) = normal.tangent_frame(&hint);
+            for v in [&tangent, &bitangent] {
+                assert!((Vec3::from(v).len() - 1.0).abs() < 1e-5);
+                assert!((v * normal).abs() < 1e-5);
+            }
+            assert!((tangent * bitangent).abs() < 1e-5);
+            assert!((Vec3::from(normal.cross(&tangent)) - Vec3::from(bitangent)).len() < 1e-5);
+            let along = hint - (Vec3::from(normal) * hint) * Vec3::from(normal);
+            if along.len() > 1e-3 {
+                assert!((Vec3::from(tangent) - Vec3::from(along.norm())).len() < 1e-5, "{:?} does not follow {:?}", tangent, hint);
+            }
+        }
+    }
+}